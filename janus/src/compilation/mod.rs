@@ -3,14 +3,13 @@ pub mod utils;
 
 use std::{
     collections::HashSet,
-    fs::{self, File, OpenOptions},
+    fs::{self, OpenOptions},
     io::Write,
     path::PathBuf,
     process::Command,
 };
 
 use console::style;
-use copy_dir::copy_dir;
 
 use crate::{
     compilation::utils::{get_output_folder, get_source_folder},
@@ -44,10 +43,13 @@ pub struct CompilationInfo {
     pub source: PathBuf,
     pub format: OutputFormat,
     pub target: CompilationTarget,
+    #[allow(dead_code)] // Not consumed by any pipeline yet.
     pub module_system: ModuleSystem,
     pub main: PathBufOrPathBufList,
     pub no_std: bool,
     pub mode: CompilationMode,
+    /// Host globals, from the `[lint]` table.
+    pub globals: Vec<String>,
 }
 
 pub struct CompilationHost {}
@@ -76,6 +78,15 @@ impl CompilationHost {
             .arg(file_path.to_str().unwrap())
             .arg("-o")
             .arg(&out);
+        // Modules are looked up from the sources root, and dependencies
+        // from their compiled objects.
+        cmd.arg("--mod")
+            .arg(&info.source)
+            .arg("--mod")
+            .arg(info.output.join("cache").join("objects"));
+        for global in info.globals.iter() {
+            cmd.arg("--global").arg(global);
+        }
         // If not the main file, skip the STD header injection
         if info.no_std || info.mode == CompilationMode::Lib {
             cmd.arg("--no-std");
@@ -133,14 +144,13 @@ impl CompilationHost {
         match info.format {
             OutputFormat::File => {
                 pipelines::FilePipeline.collect_file(
-                    &info,
-                    &objects,
+                    info,
                     &objects_base_path,
                     &target_base_path,
                     None,
                     external_modules,
                 );
-                if external_modules.len() > 0 {
+                if !external_modules.is_empty() {
                     println!("\nLinking additional artifacts...");
                     let pb = get_bar(objects.len() as u64);
                     for entry in external_modules.iter() {
@@ -171,8 +181,7 @@ impl CompilationHost {
             OutputFormat::Binary => {
                 let path = info.output.join("cache").join("main.lua");
                 pipelines::FilePipeline.collect_file(
-                    &info,
-                    &objects,
+                    info,
                     &objects_base_path,
                     &target_base_path,
                     Some(path.clone()),
@@ -196,12 +205,12 @@ impl CompilationHost {
                 #[cfg(target_family = "unix")]
                 use std::os::unix::fs::OpenOptionsExt;
                 #[cfg(target_family = "unix")]
-                let mut out = out.mode(0o711);
+                let out = out.mode(0o711);
                 let mut out = out.open(out_path).unwrap();
                 out.write_all(binaries).unwrap();
                 let main_src = fs::read(&path).unwrap();
                 out.write_all(&main_src).unwrap();
-                out.write(&main_src.len().to_le_bytes()).unwrap();
+                out.write_all(&main_src.len().to_le_bytes()).unwrap();
             }
             OutputFormat::Zip => todo!("Zip file production"),
         }
@@ -214,6 +223,7 @@ impl CompilationHost {
         dependencies: DependencyList,
         info: JanusBuild,
         meta: JanusProject,
+        globals: Vec<String>,
     ) -> Result {
         let JanusBuild {
             output,
@@ -264,6 +274,7 @@ impl CompilationHost {
             module_system,
             main,
             no_std,
+            globals,
         };
         // Those two steps cause exit if failed
         create_dist_dirs(&info.output);
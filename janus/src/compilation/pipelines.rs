@@ -2,7 +2,7 @@ use std::{
     collections::HashSet,
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use crate::display::get_bar;
@@ -15,9 +15,8 @@ impl FilePipeline {
     pub fn collect_file(
         &self,
         info: &CompilationInfo,
-        objects: &Vec<PathBuf>,
         objects_base_path: &PathBuf,
-        target_base_path: &PathBuf,
+        target_base_path: &Path,
         output: Option<PathBuf>,
         exclude: &HashSet<PathBuf>,
     ) {
@@ -50,13 +49,13 @@ impl FilePipeline {
             if exclude.contains(&entry) {
                 continue;
             }
-            let base_target = entry.strip_prefix(&objects_base_path).unwrap();
+            let base_target = entry.strip_prefix(objects_base_path).unwrap();
             let target = target_base_path.join(base_target);
             pb.set_message(format!("Linking {:?}...", &target));
             let src = fs::read_to_string(&entry).unwrap();
             let mut path_name = entry.clone();
             path_name.set_extension("");
-            let mut path_name = path_name.strip_prefix(&objects_base_path).unwrap();
+            let mut path_name = path_name.strip_prefix(objects_base_path).unwrap();
             if path_name.file_name().unwrap() == "init" {
                 path_name = path_name.parent().unwrap();
             }
@@ -71,15 +70,15 @@ impl FilePipeline {
                     path_name
                 ))
                 .unwrap();
-            file_out.write_all(&src.as_bytes()).unwrap();
-            file_out.write(b"\nend;").unwrap();
+            file_out.write_all(src.as_bytes()).unwrap();
+            file_out.write_all(b"\nend;").unwrap();
             pb.inc(1);
         }
         for entry in mains {
             pb.set_message("Collecting entry files...");
             let src = fs::read_to_string(entry).unwrap();
-            file_out.write(b"\n").unwrap();
-            file_out.write_all(&src.as_bytes()).unwrap();
+            file_out.write_all(b"\n").unwrap();
+            file_out.write_all(src.as_bytes()).unwrap();
             pb.inc(1);
         }
         pb.finish_with_message("Done");
@@ -1,7 +1,6 @@
 use std::{
     path::Path,
     process::{Command, Stdio},
-    time::Duration,
 };
 
 use copy_dir::copy_dir;
@@ -8,6 +8,7 @@ pub enum ExitCode {
     CannotCreateDistFolders,
     // CannotResolveDependencies,
     FailedCompilation,
+    FailedLint,
     // Unknown,
     Ok,
 }
@@ -21,6 +22,7 @@ impl ExitCode {
             ExitCode::CannotCreateDistFolders => exit(5),
             // ExitCode::CannotResolveDependencies => exit(6),
             ExitCode::FailedCompilation => exit(7),
+            ExitCode::FailedLint => exit(8),
             // ExitCode::Unknown => exit(-1),
             ExitCode::Ok => exit(0),
         }
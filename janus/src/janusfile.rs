@@ -34,6 +34,15 @@ pub struct JanusProject {
     pub version: Option<String>,
 }
 
+/// The `[lint]` table: The level of each rule (Eg: `shadowing = "deny"`) and
+/// the globals provided by the host.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct JanusLint {
+    pub globals: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub rules: HashMap<String, String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DependencyObject {
     pub version: Option<String>,
@@ -66,6 +75,7 @@ pub struct JanusWorkspaceConfig {
     pub project: Option<JanusProject>,
     pub build: Option<JanusBuild>,
     pub dependencies: Option<DependencyList>,
+    pub lint: Option<JanusLint>,
 }
 
 /// Resolves the janus file
@@ -79,17 +89,14 @@ fn get_janus_file(root: &Option<PathBuf>) -> std::io::Result<String> {
 }
 
 fn handle_cannot_read_file(err: &std::io::Error) -> Option<JanusWorkspaceConfig> {
-    eprintln!(
-        "Could not read the Janus.toml project file! {}",
-        err.to_string()
-    );
+    eprintln!("Could not read the Janus.toml project file! {}", err);
     None
 }
 
 fn handle_parsing_error(err: &toml::de::Error) -> Option<JanusWorkspaceConfig> {
     eprintln!(
         "There's something wrong with the Janus project file: {}",
-        err.to_string()
+        err
     );
     None
 }
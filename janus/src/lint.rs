@@ -0,0 +1,48 @@
+use std::process::Command;
+
+use crate::{
+    compilation::utils::get_source_folder,
+    errors::ExitCode,
+    janusfile::{JanusBuild, JanusLint},
+};
+
+/// Lints every source of the project with the levels of the `[lint]` table.
+pub fn lint_project(build: JanusBuild, lint: JanusLint) {
+    let source = get_source_folder(build.source);
+    let pattern = source.join("**").join("*.saturn");
+    let files = glob::glob(pattern.to_str().unwrap())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        println!("No sources to lint");
+        return;
+    }
+    let mut cmd = Command::new("saturnus");
+    cmd.arg("lint").args(&files);
+    for (rule, level) in lint.rules.iter() {
+        let flag = match level.as_str() {
+            "allow" => "--allow",
+            "warn" => "--warn",
+            "deny" => "--deny",
+            level => {
+                eprintln!(
+                    "Unknown level '{level}' for lint rule {rule}, expected allow, warn or deny"
+                );
+                ExitCode::BadJanusFile.exit();
+            }
+        };
+        cmd.arg(flag).arg(rule);
+    }
+    for global in lint.globals.unwrap_or_default() {
+        cmd.arg("--global").arg(global);
+    }
+    match cmd.status() {
+        Ok(status) if status.success() => println!("Ok - no lints denied"),
+        Ok(_) => ExitCode::FailedLint.exit(),
+        Err(err) => {
+            eprintln!("Could not run the linter, {}", err);
+            ExitCode::FailedLint.exit();
+        }
+    }
+}
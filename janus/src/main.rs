@@ -4,6 +4,7 @@ mod dir;
 mod display;
 mod errors;
 mod janusfile;
+mod lint;
 
 use std::{collections::HashMap, path::PathBuf};
 
@@ -36,6 +37,8 @@ enum Order {
     Run,
     /// Cleans the build cache only
     Clean,
+    /// Checks the sources against the lint rules of Janus.toml
+    Lint,
 }
 
 fn handle_compilation_error(err: CompilationError) {
@@ -49,22 +52,26 @@ fn process_build(args: Args) {
             project,
             build,
             dependencies,
+            lint,
         }: JanusWorkspaceConfig = workspace;
         let dependencies = dependencies.unwrap_or_default();
         let build = build.unwrap_or_default();
         let project = project.unwrap_or_default();
+        let globals = lint.unwrap_or_default().globals.unwrap_or_default();
         let result = match project_type.as_str() {
             "lib" => CompilationHost::new().compile(
                 compilation::CompilationMode::Lib,
                 dependencies,
                 build,
                 project,
+                globals,
             ),
             "bin" => CompilationHost::new().compile(
                 compilation::CompilationMode::Bin,
                 dependencies,
                 build,
                 project,
+                globals,
             ),
             _ => {
                 eprintln!("Invalid project type {}!", project_type);
@@ -110,6 +117,7 @@ fn init_project() {
             modules: None,
         }),
         dependencies: Some(HashMap::new()),
+        lint: None,
     };
     let out = toml::to_string_pretty(&janus).unwrap();
     if let Err(e) = std::fs::write("Janus.toml", out) {
@@ -119,7 +127,7 @@ fn init_project() {
             e
         );
     }
-    if let Err(_) = std::fs::write(".gitignore", "/dist\n*.lua") {
+    if std::fs::write(".gitignore", "/dist\n*.lua").is_err() {
         eprintln!(
             "{}",
             style("Could not create the .gitignore file")
@@ -145,11 +153,12 @@ fn init_project() {
             e
         );
     }
-    if let Err(_) = std::process::Command::new("git")
+    if std::process::Command::new("git")
         .arg("init")
         .arg("-b")
         .arg("main")
         .output()
+        .is_err()
     {
         eprintln!(
             "{}",
@@ -167,6 +176,15 @@ fn main() {
         Order::Build => process_build(args),
         Order::Run => todo!("Not done"),
         Order::Init => init_project(),
+        Order::Lint => {
+            let Some(info) = JanusWorkspaceConfig::parse_janus_file(&args.path) else {
+                ExitCode::BadJanusFile.exit();
+            };
+            lint::lint_project(
+                info.build.unwrap_or_default(),
+                info.lint.unwrap_or_default(),
+            );
+        }
         Order::Clean => {
             let info = JanusWorkspaceConfig::parse_janus_file(&args.path).unwrap();
             std::fs::remove_dir_all(
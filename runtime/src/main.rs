@@ -51,10 +51,8 @@ fn main() -> io::Result<()> {
         ctx.load(&script).exec()?;
         Ok(())
     });
-    if let Err(err) = res {
-        if let rlua::Error::RuntimeError(message) = err {
-            eprintln!("{}", style(format!("Runtime error! {message}")).red());
-        }
+    if let Err(rlua::Error::RuntimeError(message)) = res {
+        eprintln!("{}", style(format!("Runtime error! {message}")).red());
     }
     Ok(())
 }
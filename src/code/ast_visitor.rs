@@ -18,6 +18,8 @@ pub trait Visitor {
     fn visit_binary(&self, ctx: Builder, expr: &BinaryExpression) -> Result;
     fn visit_unary(&self, ctx: Builder, expr: &UnaryExpression) -> Result;
     fn visit_spread(&self, ctx: Builder, expr: &SpreadExpression) -> Result;
+    fn visit_yield(&self, ctx: Builder, expr: &Yield) -> Result;
+    fn visit_await(&self, ctx: Builder, expr: &Await) -> Result;
     fn visit_wrapped_expression(&self, ctx: Builder, expr: &Expression) -> Result;
     fn visit_identifier(&self, ctx: Builder, expr: &Identifier) -> Result;
 
@@ -27,12 +29,14 @@ pub trait Visitor {
     fn visit_unit(&self, ctx: Builder) -> Result;
     fn visit_number(&self, ctx: Builder, expr: &Number) -> Result;
     fn visit_string(&self, ctx: Builder, expr: &StringLiteral) -> Result;
+    fn visit_interpolated_string(&self, ctx: Builder, expr: &InterpolatedString) -> Result;
     fn visit_table(&self, ctx: Builder, expr: &Table) -> Result;
     fn visit_vector(&self, ctx: Builder, expr: &Vector) -> Result;
 
     // Statements
     fn visit_return(&self, ctx: Builder, stmt: &Return) -> Result;
     fn visit_class(&self, ctx: Builder, stmt: &Class) -> Result;
+    fn visit_enum(&self, ctx: Builder, stmt: &Enum) -> Result;
     fn visit_fn(&self, ctx: Builder, stmt: &Function) -> Result;
     fn visit_assignment(&self, ctx: Builder, stmt: &Assignment) -> Result;
     fn visit_declaration(&self, ctx: Builder, stmt: &Let) -> Result;
@@ -47,8 +51,13 @@ pub trait Visitor {
 
     // Conditionals
     fn visit_if(&self, ctx: Builder, expr: &If) -> Result;
+    fn visit_if_expression(&self, ctx: Builder, expr: &IfExpression) -> Result;
     fn visit_match(&self, ctx: Builder, expr: &Match) -> Result;
 
+    // Error handling
+    fn visit_try(&self, ctx: Builder, stmt: &Try) -> Result;
+    fn visit_defer(&self, ctx: Builder, stmt: &Defer) -> Result;
+
     fn visit_block_expression(&self, ctx: Builder, expr: &Do) -> Result;
     fn visit_script(&self, ctx: Builder, script: &Script) -> Result;
 
@@ -60,15 +69,19 @@ pub trait Visitor {
     fn visit_expression(&self, ctx: Builder, expression: &Expression) -> Result {
         match expression {
             Expression::Lambda(e) => self.visit_lambda(ctx, e),
+            Expression::If(e) => self.visit_if_expression(ctx, e),
             Expression::Reference(e) => self.visit_reference(ctx, e),
             Expression::Call(e) => self.visit_call(ctx, e),
             Expression::Tuple(e) => self.visit_tuple(ctx, e),
             Expression::Number(e) => self.visit_number(ctx, e),
             Expression::String(e) => self.visit_string(ctx, e),
+            Expression::Interpolated(e) => self.visit_interpolated_string(ctx, e),
             Expression::Unit => self.visit_unit(ctx),
             Expression::Binary(e) => self.visit_binary(ctx, e),
             Expression::Unary(e) => self.visit_unary(ctx, e),
             Expression::Spread(e) => self.visit_spread(ctx, e),
+            Expression::Yield(e) => self.visit_yield(ctx, e),
+            Expression::Await(e) => self.visit_await(ctx, e),
             Expression::Table(e) => self.visit_table(ctx, e),
             Expression::Vector(e) => self.visit_vector(ctx, e),
             Expression::Tuple1(e) => self.visit_wrapped_expression(ctx, e),
@@ -81,11 +94,14 @@ pub trait Visitor {
         match statement {
             ast::Statement::MacroDecorator(e) => self.visit_macro_decorator(ctx, e),
             ast::Statement::If(e) => self.visit_if(ctx, e),
+            ast::Statement::Try(e) => self.visit_try(ctx, e),
+            ast::Statement::Defer(e) => self.visit_defer(ctx, e),
             ast::Statement::For(e) => self.visit_for(ctx, e),
             ast::Statement::Loop(e) => self.visit_loop(ctx, e),
             ast::Statement::While(e) => self.visit_while(ctx, e),
             ast::Statement::Return(e) => self.visit_return(ctx, e),
             ast::Statement::Class(e) => self.visit_class(ctx, e),
+            ast::Statement::Enum(e) => self.visit_enum(ctx, e),
             ast::Statement::Function(e) => self.visit_fn(ctx, e),
             ast::Statement::Assignment(e) => self.visit_assignment(ctx, e),
             ast::Statement::Let(e) => self.visit_declaration(ctx, e),
@@ -99,6 +115,6 @@ pub trait Visitor {
         script
             .statements
             .iter()
-            .fold(Ok(ctx), |ctx, stmt| self.visit_statement(ctx?, stmt))
+            .try_fold(ctx, |ctx, stmt| self.visit_statement(ctx, stmt))
     }
 }
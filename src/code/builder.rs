@@ -29,7 +29,7 @@ impl std::fmt::Debug for UnevenIndentationError {
 ///     .collect()
 /// ```
 /// Yields:
-/// ```
+/// ```text
 /// hello
 ///   my
 /// world
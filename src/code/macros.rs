@@ -19,7 +19,7 @@ pub trait Macro {
     fn expand_call(&self, ast: &ast::MacroCallExpression) -> ast::Expression;
 }
 
-struct PanicMacro(InputFileInfo);
+struct PanicMacro;
 impl Macro for PanicMacro {
     fn expand_call(&self, ast: &ast::MacroCallExpression) -> ast::Expression {
         Expression::Call(Box::new(CallExpression {
@@ -29,28 +29,31 @@ impl Macro for PanicMacro {
                     tail: vec![],
                 }),
                 arguments: ast.arguments.clone().unwrap_or(vec![]),
+                optional: false,
             },
             tail: vec![],
         }))
     }
 }
 
+/// The path given as the string argument of an include macro.
+fn path_of(value: &StringLiteral) -> String {
+    String::from_utf8_lossy(&value.value).into_owned()
+}
+
 struct IncludeTextMacro;
 impl Macro for IncludeTextMacro {
     fn expand_call(&self, ast: &ast::MacroCallExpression) -> ast::Expression {
         if let Some(args) = &ast.arguments {
-            if let Some(arg) = args.first() {
-                if let Expression::String(value) = arg {
-                    if value.prefix.is_some() {
-                        panic!("include_text!() string argument cannot have prefix!");
-                    }
-                    let value = std::fs::read_to_string(&value.value).unwrap();
-                    let value = value.replace("\"", "\\\"");
-                    return Expression::String(StringLiteral {
-                        prefix: None,
-                        value,
-                    });
+            if let Some(Expression::String(value)) = args.first() {
+                if value.prefix.is_some() {
+                    panic!("include_text!() string argument cannot have prefix!");
                 }
+                let value = std::fs::read_to_string(path_of(value)).unwrap();
+                return Expression::String(StringLiteral {
+                    prefix: None,
+                    value: value.into_bytes(),
+                });
             }
         }
         panic!("include_text!() macro needs to be called with a constant string argument!");
@@ -61,23 +64,21 @@ struct IncludeBytesMacro;
 impl Macro for IncludeBytesMacro {
     fn expand_call(&self, ast: &ast::MacroCallExpression) -> ast::Expression {
         if let Some(args) = &ast.arguments {
-            if let Some(arg) = args.first() {
-                if let ast::Expression::String(value) = arg {
-                    if value.prefix.is_some() {
-                        panic!("include_bytes!() string argument cannot have prefix!");
-                    }
-                    let value = std::fs::read(&value.value).unwrap();
-                    let expressions = value
-                        .iter()
-                        .map(|int| {
-                            ast::Expression::Number(ast::Number {
-                                value: ast::NumberVariant::Hexadecimal(int.clone() as i64),
-                                postfix: None,
-                            })
-                        })
-                        .collect::<Vec<ast::Expression>>();
-                    return ast::Expression::Vector(ast::Vector { expressions });
+            if let Some(ast::Expression::String(value)) = args.first() {
+                if value.prefix.is_some() {
+                    panic!("include_bytes!() string argument cannot have prefix!");
                 }
+                let value = std::fs::read(path_of(value)).unwrap();
+                let expressions = value
+                    .iter()
+                    .map(|int| {
+                        ast::Expression::Number(ast::Number {
+                            value: ast::NumberVariant::Hexadecimal(*int as i64),
+                            postfix: None,
+                        })
+                    })
+                    .collect::<Vec<ast::Expression>>();
+                return ast::Expression::Vector(ast::Vector { expressions });
             }
         }
         panic!("include_bytes!() macro needs to be called with a constant string argument!");
@@ -88,19 +89,17 @@ struct IncludeBase64Macro;
 impl Macro for IncludeBase64Macro {
     fn expand_call(&self, ast: &ast::MacroCallExpression) -> ast::Expression {
         if let Some(args) = &ast.arguments {
-            if let Some(arg) = args.first() {
-                if let ast::Expression::String(value) = arg {
-                    use base64::Engine;
-                    if value.prefix.is_some() {
-                        panic!("include_base64!() string argument cannot have prefix!");
-                    }
-                    let value = std::fs::read(&value.value).unwrap();
-                    let value = base64::engine::general_purpose::STANDARD.encode(value);
-                    return Expression::String(StringLiteral {
-                        prefix: None,
-                        value,
-                    });
+            if let Some(ast::Expression::String(value)) = args.first() {
+                use base64::Engine;
+                if value.prefix.is_some() {
+                    panic!("include_base64!() string argument cannot have prefix!");
                 }
+                let value = std::fs::read(path_of(value)).unwrap();
+                let value = base64::engine::general_purpose::STANDARD.encode(value);
+                return Expression::String(StringLiteral {
+                    prefix: None,
+                    value: value.into_bytes(),
+                });
             }
         }
         panic!("include_base64!() macro needs to be called with a constant string argument!");
@@ -113,7 +112,7 @@ impl Macro for FileMacro {
         let value = self.0.full_path.as_os_str().to_string_lossy().to_string();
         let value = value.replace("\\", "/");
         ast::Expression::String(ast::StringLiteral {
-            value,
+            value: value.into_bytes(),
             prefix: None,
         })
     }
@@ -125,7 +124,7 @@ pub struct MacroHost {
 impl MacroHost {
     pub fn new(info: InputFileInfo) -> Self {
         let mut macros: HashMap<String, Box<dyn Macro>> = HashMap::new();
-        macros.insert("panic".into(), Box::new(PanicMacro(info.clone())));
+        macros.insert("panic".into(), Box::new(PanicMacro));
         macros.insert("file".into(), Box::new(FileMacro(info.clone())));
         macros.insert("include_str".into(), Box::new(IncludeTextMacro));
         macros.insert("include_bytes".into(), Box::new(IncludeBytesMacro));
@@ -2,3 +2,4 @@ pub mod ast_visitor;
 pub mod builder;
 pub mod info;
 pub mod macros;
+pub mod scope_analysis;
@@ -0,0 +1,1016 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    fmt::{self, Display},
+};
+
+use crate::{
+    lint::{Finding, Rule},
+    parser::{
+        ast::{self, Identifier},
+        helpers::{generate_operator_function_name, is_native_operator},
+        Script,
+    },
+};
+
+use super::{
+    ast_visitor::{Result, VisitError, Visitor},
+    builder::Builder,
+};
+
+/// Globals of the Lua host that scripts may read without declaring them.
+pub const LUA_GLOBALS: [&str; 37] = [
+    "_G",
+    "_ENV",
+    "_VERSION",
+    "assert",
+    "collectgarbage",
+    "coroutine",
+    "debug",
+    "dofile",
+    "error",
+    "getmetatable",
+    "io",
+    "ipairs",
+    "jit",
+    "load",
+    "loadfile",
+    "math",
+    "next",
+    "os",
+    "package",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "require",
+    "select",
+    "setmetatable",
+    "string",
+    "table",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "utf8",
+    "xpcall",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A name read by the script that no scope declares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The identifier as emitted, operators are already translated.
+    pub name: String,
+    pub message: String,
+}
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// Raised by hosts that refuse to compile scripts with error diagnostics.
+#[derive(Debug)]
+pub struct UndeclaredNames(pub Vec<Diagnostic>);
+impl Display for UndeclaredNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self.0.iter().map(Diagnostic::to_string);
+        write!(f, "{}", lines.collect::<Vec<_>>().join("\n"))
+    }
+}
+impl std::error::Error for UndeclaredNames {}
+
+/// # Scope analyzer
+///
+/// Resolves every identifier read by a script against the bindings in scope
+/// (`let`, `fn`, `class`, `enum`, arguments, destructuring, `use` and the
+/// `local` declarations of Lua extern blocks), so misspelled names are
+/// reported instead of silently compiling to Lua globals.
+///
+/// Names assigned without a declaration are the script's own globals, and
+/// reading them anywhere is fine. Globals set by the host have to be allowed
+/// explicitly, on top of the standard [`LUA_GLOBALS`].
+///
+/// As it walks the whole script anyway, the analyzer also collects the
+/// findings of the [lint rules](crate::lint::Rule), skipping the statements
+/// decorated with `#[allow(rule)]`.
+///
+/// Example:
+/// ```rs
+/// let diagnostics = ScopeAnalyzer::new()
+///     .allow_global("add")
+///     .severity(Severity::Error)
+///     .analyze(&script)?;
+/// ```
+pub struct ScopeAnalyzer {
+    severity: Severity,
+    allowed: HashSet<String>,
+    scopes: RefCell<Vec<Vec<Binding>>>,
+    /// Names assigned without being declared first.
+    globals: RefCell<HashSet<String>>,
+    /// Reads that no scope resolved, as (name, message, position) in reading
+    /// order.
+    unresolved: RefCell<Vec<(String, String, usize)>>,
+    findings: RefCell<Vec<Finding>>,
+    /// Where the statement being visited starts in the source.
+    position: Cell<usize>,
+    /// Rules silenced by the `#[allow(...)]` decorators being visited.
+    allowed_rules: RefCell<Vec<String>>,
+    /// The functions and methods being visited, named in the findings.
+    context: RefCell<Vec<String>>,
+}
+
+/// How a name takes part in the lints once declared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Declaration {
+    /// `let`, `for` and `use` targets, reported if never read.
+    Variable,
+    /// Arguments, functions, classes, enums and exported names.
+    Item,
+    /// Bound by the compiler or by extern code, never reported.
+    Implicit,
+}
+
+struct Binding {
+    name: String,
+    position: usize,
+    read: bool,
+    report_unused: bool,
+}
+
+impl Default for ScopeAnalyzer {
+    fn default() -> Self {
+        ScopeAnalyzer {
+            severity: Severity::Warning,
+            allowed: LUA_GLOBALS.iter().map(|name| name.to_string()).collect(),
+            scopes: RefCell::new(vec![]),
+            globals: RefCell::new(HashSet::new()),
+            unresolved: RefCell::new(vec![]),
+            findings: RefCell::new(vec![]),
+            position: Cell::new(0),
+            allowed_rules: RefCell::new(vec![]),
+            context: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl ScopeAnalyzer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allows reading a global provided by the host.
+    pub fn allow_global<N: Into<String>>(mut self, name: N) -> Self {
+        self.allowed.insert(name.into());
+        self
+    }
+
+    pub fn allow_globals<I, N>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = N>,
+        N: Into<String>,
+    {
+        names
+            .into_iter()
+            .fold(self, |analyzer, name| analyzer.allow_global(name))
+    }
+
+    /// The severity of the reported reads, warnings by default.
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Reports each undeclared name once, in the order they are first read.
+    pub fn analyze(&self, script: &Script) -> std::result::Result<Vec<Diagnostic>, VisitError> {
+        self.walk(script)?;
+        let diagnostics = self
+            .undeclared()
+            .into_iter()
+            .map(|(name, message, _)| Diagnostic {
+                severity: self.severity,
+                name,
+                message,
+            })
+            .collect();
+        Ok(diagnostics)
+    }
+
+    /// The findings of every lint rule, undeclared names last.
+    pub fn findings(&self, script: &Script) -> std::result::Result<Vec<Finding>, VisitError> {
+        self.walk(script)?;
+        let undeclared = self
+            .undeclared()
+            .into_iter()
+            .map(|(_, message, position)| Finding {
+                rule: Rule::UndeclaredNames,
+                message,
+                position,
+            });
+        Ok(self.findings.take().into_iter().chain(undeclared).collect())
+    }
+
+    fn walk(&self, script: &Script) -> std::result::Result<(), VisitError> {
+        self.scopes.replace(vec![vec![]]);
+        self.globals.replace(HashSet::new());
+        self.unresolved.replace(vec![]);
+        self.findings.replace(vec![]);
+        self.position.set(0);
+        self.visit_block(Builder::new(""), script)?;
+        Ok(())
+    }
+
+    /// The unresolved reads that are not globals, once per name.
+    fn undeclared(&self) -> Vec<(String, String, usize)> {
+        let globals = self.globals.borrow();
+        let mut reported = HashSet::new();
+        self.unresolved
+            .borrow()
+            .iter()
+            .filter(|(name, ..)| !globals.contains(name) && !self.allowed.contains(name))
+            .filter(|(name, ..)| reported.insert(name.clone()))
+            .cloned()
+            .collect()
+    }
+
+    fn allows(&self, rule: Rule) -> bool {
+        self.allowed_rules
+            .borrow()
+            .iter()
+            .any(|name| name == rule.name())
+    }
+
+    /// The message, naming the function it was found in.
+    fn located(&self, message: String) -> String {
+        let context = self.context.borrow();
+        if context.is_empty() {
+            message
+        } else {
+            format!("{message}, in `{}`", context.join("."))
+        }
+    }
+
+    fn report(&self, rule: Rule, message: String) {
+        if !self.allows(rule) {
+            let message = self.located(message);
+            self.findings.borrow_mut().push(Finding {
+                rule,
+                message,
+                position: self.position.get(),
+            });
+        }
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.scopes
+            .borrow()
+            .iter()
+            .any(|scope| scope.iter().any(|binding| binding.name == name))
+    }
+
+    fn declare(&self, name: &str, declaration: Declaration) {
+        // Nested array patterns have no name of their own.
+        if name.is_empty() {
+            return;
+        }
+        let checked = declaration != Declaration::Implicit && !name.starts_with('_');
+        if checked && self.is_declared(name) {
+            self.report(
+                Rule::Shadowing,
+                format!("`{name}` shadows an earlier declaration"),
+            );
+        }
+        let binding = Binding {
+            name: name.to_owned(),
+            position: self.position.get(),
+            read: false,
+            report_unused: checked
+                && declaration == Declaration::Variable
+                && !self.allows(Rule::UnusedVariables),
+        };
+        if let Some(scope) = self.scopes.borrow_mut().last_mut() {
+            scope.push(binding);
+        }
+    }
+
+    /// Marks the innermost binding of the name as read, if any.
+    fn mark_read(&self, name: &str) -> bool {
+        let mut scopes = self.scopes.borrow_mut();
+        let binding = scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|binding| binding.name == name));
+        match binding {
+            Some(binding) => {
+                binding.read = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn read(&self, name: &str, shown: String) {
+        if !self.mark_read(name) && !self.allows(Rule::UndeclaredNames) {
+            let message = self.located(format!("{shown} is not declared"));
+            self.unresolved
+                .borrow_mut()
+                .push((name.to_owned(), message, self.position.get()));
+        }
+    }
+
+    fn read_identifier(&self, id: &Identifier) {
+        // Literals and the `break` keyword, which the grammar leaves as plain
+        // identifiers. There is no `continue`, so it's reported like any name.
+        if matches!(id.0.as_str(), "true" | "false" | "nil" | "break") {
+            return;
+        }
+        self.read(&id.0, format!("`{}`", id.0));
+    }
+
+    /// Runs `f` in a new scope, reporting its bindings that were never read.
+    fn scoped<F>(&self, ctx: Builder, f: F) -> Result
+    where
+        F: FnOnce(Builder) -> Result,
+    {
+        self.scopes.borrow_mut().push(vec![]);
+        let result = f(ctx);
+        let scope = self.scopes.borrow_mut().pop().unwrap_or_default();
+        for binding in scope.iter().filter(|b| b.report_unused && !b.read) {
+            let message = self.located(format!("`{}` is never read", binding.name));
+            self.findings.borrow_mut().push(Finding {
+                rule: Rule::UnusedVariables,
+                message,
+                position: binding.position,
+            });
+        }
+        result
+    }
+
+    /// Runs `f` inside the named function or method.
+    fn within<F>(&self, name: &str, f: F) -> Result
+    where
+        F: FnOnce() -> Result,
+    {
+        self.context.borrow_mut().push(name.to_owned());
+        let result = f();
+        self.context.borrow_mut().pop();
+        result
+    }
+
+    /// Reads the default values of the targets, then declares them.
+    fn visit_destructuring(
+        &self,
+        ctx: Builder,
+        expr: &ast::Destructuring,
+        declaration: Declaration,
+    ) -> Result {
+        expr.targets.iter().try_fold(ctx, |ctx, seg| match seg {
+            ast::DestructuringSegment::Identifier(id) | ast::DestructuringSegment::Rest(id) => {
+                self.declare(&id.0, declaration);
+                Ok(ctx)
+            }
+            ast::DestructuringSegment::Default((id, default)) => {
+                let ctx = self.visit_expression(ctx, default)?;
+                self.declare(&id.0, declaration);
+                Ok(ctx)
+            }
+            ast::DestructuringSegment::Destructuring((_, expr)) => {
+                self.visit_destructuring(ctx, expr, declaration)
+            }
+        })
+    }
+
+    fn visit_target(
+        &self,
+        ctx: Builder,
+        target: &ast::AssignmentTarget,
+        declaration: Declaration,
+    ) -> Result {
+        match target {
+            ast::AssignmentTarget::Destructuring(expr) => {
+                self.visit_destructuring(ctx, expr, declaration)
+            }
+            ast::AssignmentTarget::Identifier(id) => {
+                self.declare(&id.0, declaration);
+                Ok(ctx)
+            }
+        }
+    }
+
+    /// Visits a function, with `implicit` bound along its arguments.
+    fn visit_function(
+        &self,
+        ctx: Builder,
+        implicit: &[&str],
+        arguments: &[ast::Argument],
+        body: &ast::ScriptOrExpression,
+    ) -> Result {
+        // The `it` and `rest` of `f in { ... }` blocks are not written.
+        let arguments_as = if is_block_argument(arguments) {
+            Declaration::Implicit
+        } else {
+            Declaration::Item
+        };
+        self.scoped(ctx, |ctx| {
+            implicit
+                .iter()
+                .for_each(|name| self.declare(name, Declaration::Implicit));
+            arguments
+                .iter()
+                .for_each(|arg| self.declare(&arg.name.0, arguments_as));
+            let ctx = arguments.iter().try_fold(ctx, |ctx, arg| {
+                let ctx = match &arg.default {
+                    Some(default) => self.visit_expression(ctx, default)?,
+                    None => ctx,
+                };
+                match &arg.pattern {
+                    Some(pattern) => self.visit_destructuring(ctx, pattern, arguments_as),
+                    None => Ok(ctx),
+                }
+            })?;
+            match body {
+                ast::ScriptOrExpression::Script(body) => self.visit_block(ctx, body),
+                ast::ScriptOrExpression::Expression(body) => self.visit_expression(ctx, body),
+            }
+        })
+    }
+
+    fn visit_method(&self, ctx: Builder, class: &ast::Class, f: &ast::Function) -> Result {
+        let implicit: &[&str] = match class.parent {
+            Some(_) => &["Self", "super"],
+            None => &["Self"],
+        };
+        let body = ast::ScriptOrExpression::Script(f.body.clone());
+        let ctx = self.within(&format!("{}.{}", class.name.0, f.name.0), || {
+            self.visit_function(ctx, implicit, &f.arguments, &body)
+        })?;
+        self.visit_decorators(ctx, &f.decorators)
+    }
+
+    fn visit_decorators(&self, ctx: Builder, decorators: &[ast::Decorator]) -> Result {
+        decorators
+            .iter()
+            .try_fold(ctx, |ctx, dec| self.visit_call(ctx, &dec.target))
+    }
+
+    fn visit_segment(&self, ctx: Builder, segment: &ast::MemberSegment) -> Result {
+        match segment {
+            ast::MemberSegment::Computed(key) | ast::MemberSegment::OptionalComputed(key) => {
+                self.visit_expression(ctx, key)
+            }
+            _ => Ok(ctx),
+        }
+    }
+
+    /// Visits the block of a statement, which should not be left empty.
+    fn visit_body(&self, ctx: Builder, what: &str, body: &Script) -> Result {
+        if body.statements.is_empty() {
+            self.report(Rule::EmptyBlocks, format!("Empty `{what}` block"));
+        }
+        self.visit_block(ctx, body)
+    }
+
+    fn visit_statements(&self, ctx: Builder, script: &Script) -> Result {
+        let returns = script
+            .statements
+            .iter()
+            .position(|stmt| matches!(stmt, ast::Statement::Return(_)));
+        let outer = self.position.get();
+        if let Some(i) = returns.filter(|i| i + 1 < script.statements.len()) {
+            // Reported where the unreachable code starts.
+            if let Some(position) = script.positions.get(i + 1) {
+                self.position.set(*position);
+            }
+            self.report(
+                Rule::UnreachableCode,
+                "Unreachable code after `return`".into(),
+            );
+        }
+        let result = script
+            .statements
+            .iter()
+            .enumerate()
+            .try_fold(ctx, |ctx, (i, stmt)| {
+                if let Some(position) = script.positions.get(i) {
+                    self.position.set(*position);
+                }
+                self.visit_statement(ctx, stmt)
+            });
+        self.position.set(outer);
+        result
+    }
+
+    fn visit_value_block(&self, ctx: Builder, block: &ast::ValueBlock) -> Result {
+        self.scoped(ctx, |ctx| {
+            let ctx = self.visit_statements(ctx, &block.body)?;
+            match &block.value {
+                Some(value) => self.visit_expression(ctx, value),
+                None => Ok(ctx),
+            }
+        })
+    }
+}
+
+/// The side compared with `()` by the condition, if it is such comparison.
+fn nil_compared<'a>(condition: &'a ast::Expression, operator: &str) -> Option<&'a ast::Expression> {
+    let is_nil = |e: &ast::Expression| match e {
+        ast::Expression::Unit => true,
+        ast::Expression::Identifier(id) => id.0 == "nil",
+        _ => false,
+    };
+    match condition {
+        ast::Expression::Binary(expr) if expr.operator.0 == operator => {
+            if is_nil(&expr.right) {
+                Some(&expr.left)
+            } else if is_nil(&expr.left) {
+                Some(&expr.right)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The dotted path of a plain reference, like `a.b.c`.
+fn reference_path(expr: &ast::Expression) -> Option<String> {
+    match expr {
+        ast::Expression::Identifier(id) => Some(id.0.clone()),
+        ast::Expression::Reference(expr) => {
+            let head = reference_path(&expr.head)?;
+            expr.tail
+                .iter()
+                .try_fold(head, |path, segment| match segment {
+                    ast::MemberSegment::Identifier(id) => Some(format!("{path}.{}", id.0)),
+                    _ => None,
+                })
+        }
+        _ => None,
+    }
+}
+
+fn same_reference(a: &ast::Expression, b: &ast::Expression) -> bool {
+    matches!((reference_path(a), reference_path(b)), (Some(a), Some(b)) if a == b)
+}
+
+/// True for the arguments that `f in { ... }` gives to the block.
+fn is_block_argument(arguments: &[ast::Argument]) -> bool {
+    matches!(
+        arguments,
+        [it, rest] if it.name.0 == "it" && rest.name.0 == "rest" && rest.spread
+    )
+}
+
+/// The rules named by an `#[allow(a, b)]` decorator.
+fn allowed_rules(macros: &[ast::IdentifierOrCall]) -> Vec<String> {
+    macros
+        .iter()
+        .filter_map(|mac| match mac {
+            ast::IdentifierOrCall::Call(call) => Some(&call.head),
+            ast::IdentifierOrCall::Identifier(_) => None,
+        })
+        .filter(|call| {
+            call.callee.as_ref().is_some_and(|callee| {
+                callee.tail.is_empty()
+                    && matches!(&callee.head, ast::Expression::Identifier(id) if id.0 == "allow")
+            })
+        })
+        .flat_map(|call| call.arguments.iter())
+        .filter_map(reference_path)
+        .collect()
+}
+
+/// The identifiers, keywords and symbols of Lua code, ignoring whitespace.
+fn lua_tokens(src: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphabetic() || c == '_' {
+            let mut token = c.to_string();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                token.push(c);
+            }
+            tokens.push(token);
+        } else if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+    }
+    tokens
+}
+
+/// The names declared by `local` and `function` statements of Lua code.
+fn lua_declarations(tokens: &[String]) -> Vec<String> {
+    let mut names = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        match (tokens[i].as_str(), tokens.get(i + 1), tokens.get(i + 2)) {
+            ("local", Some(keyword), Some(name)) if keyword == "function" => {
+                names.push(name.clone());
+                i += 3;
+            }
+            ("local", Some(_), _) => {
+                i += 1;
+                while let Some(name) = tokens.get(i) {
+                    names.push(name.clone());
+                    if tokens.get(i + 1).map(String::as_str) != Some(",") {
+                        break;
+                    }
+                    i += 2;
+                }
+            }
+            // Only plain names, `function a.b()` declares nothing.
+            ("function", Some(name), next)
+                if next.map(String::as_str) != Some(".")
+                    && next.map(String::as_str) != Some(":") =>
+            {
+                names.push(name.clone());
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    names
+}
+
+impl Visitor for ScopeAnalyzer {
+    fn visit_reference(&self, ctx: Builder, expr: &ast::MemberExpression) -> Result {
+        let ctx = self.visit_expression(ctx, &expr.head)?;
+        expr.tail
+            .iter()
+            .try_fold(ctx, |ctx, segment| self.visit_segment(ctx, segment))
+    }
+
+    fn visit_call(&self, ctx: Builder, expr: &ast::CallExpression) -> Result {
+        let ctx = match &expr.head.callee {
+            Some(callee) => self.visit_reference(ctx, callee)?,
+            None => ctx,
+        };
+        let ctx = expr
+            .head
+            .arguments
+            .iter()
+            .try_fold(ctx, |ctx, arg| self.visit_expression(ctx, arg))?;
+        expr.tail.iter().try_fold(ctx, |ctx, elem| match elem {
+            ast::CallExpressionVariant::Call(call) => call
+                .arguments
+                .iter()
+                .try_fold(ctx, |ctx, arg| self.visit_expression(ctx, arg)),
+            ast::CallExpressionVariant::Member(segment) => self.visit_segment(ctx, segment),
+        })
+    }
+
+    fn visit_binary(&self, ctx: Builder, expr: &ast::BinaryExpression) -> Result {
+        let op = &expr.operator.0;
+        if !is_native_operator(op) {
+            let name = generate_operator_function_name(op.clone());
+            self.read(&name, format!("Operator `{op}`"));
+        }
+        let ctx = self.visit_expression(ctx, &expr.left)?;
+        self.visit_expression(ctx, &expr.right)
+    }
+
+    fn visit_unary(&self, ctx: Builder, expr: &ast::UnaryExpression) -> Result {
+        self.visit_expression(ctx, &expr.expression)
+    }
+
+    fn visit_spread(&self, ctx: Builder, expr: &ast::SpreadExpression) -> Result {
+        self.visit_expression(ctx, &expr.expression)
+    }
+
+    fn visit_yield(&self, ctx: Builder, expr: &ast::Yield) -> Result {
+        match &expr.value {
+            Some(value) => self.visit_expression(ctx, value),
+            None => Ok(ctx),
+        }
+    }
+
+    fn visit_await(&self, ctx: Builder, expr: &ast::Await) -> Result {
+        self.visit_expression(ctx, &expr.value)
+    }
+
+    fn visit_wrapped_expression(&self, ctx: Builder, expr: &ast::Expression) -> Result {
+        self.visit_expression(ctx, expr)
+    }
+
+    fn visit_identifier(&self, ctx: Builder, expr: &ast::Identifier) -> Result {
+        self.read_identifier(expr);
+        Ok(ctx)
+    }
+
+    fn visit_lambda(&self, ctx: Builder, expr: &ast::Lambda) -> Result {
+        self.visit_function(ctx, &[], &expr.arguments, &expr.body)
+    }
+
+    fn visit_tuple(&self, ctx: Builder, expr: &ast::Tuple) -> Result {
+        expr.0
+            .iter()
+            .try_fold(ctx, |ctx, value| self.visit_expression(ctx, value))
+    }
+
+    fn visit_unit(&self, ctx: Builder) -> Result {
+        Ok(ctx)
+    }
+
+    fn visit_number(&self, ctx: Builder, expr: &ast::Number) -> Result {
+        if let Some(postfix) = &expr.postfix {
+            self.read_identifier(postfix);
+        }
+        Ok(ctx)
+    }
+
+    fn visit_string(&self, ctx: Builder, expr: &ast::StringLiteral) -> Result {
+        if let Some(prefix) = &expr.prefix {
+            self.read_identifier(prefix);
+        }
+        Ok(ctx)
+    }
+
+    fn visit_interpolated_string(&self, ctx: Builder, expr: &ast::InterpolatedString) -> Result {
+        if let Some(prefix) = &expr.prefix {
+            self.read_identifier(prefix);
+        }
+        expr.parts.iter().try_fold(ctx, |ctx, part| match part {
+            ast::StringPart::Literal(_) => Ok(ctx),
+            ast::StringPart::Expression(value) => self.visit_expression(ctx, value),
+        })
+    }
+
+    fn visit_table(&self, ctx: Builder, expr: &ast::Table) -> Result {
+        expr.key_values.iter().try_fold(ctx, |ctx, (key, value)| {
+            let ctx = match key {
+                ast::TableKeyExpression::Identifier(_) => ctx,
+                ast::TableKeyExpression::Expression(key) => self.visit_expression(ctx, key)?,
+                ast::TableKeyExpression::Implicit(key) => self.visit_identifier(ctx, key)?,
+            };
+            match value {
+                Some(value) => self.visit_expression(ctx, value),
+                None => Ok(ctx),
+            }
+        })
+    }
+
+    fn visit_vector(&self, ctx: Builder, expr: &ast::Vector) -> Result {
+        expr.expressions
+            .iter()
+            .try_fold(ctx, |ctx, value| self.visit_expression(ctx, value))
+    }
+
+    fn visit_return(&self, ctx: Builder, stmt: &ast::Return) -> Result {
+        self.visit_expression(ctx, &stmt.value)
+    }
+
+    fn visit_class(&self, ctx: Builder, stmt: &ast::Class) -> Result {
+        if let Some(parent) = &stmt.parent {
+            self.read_identifier(parent);
+        }
+        self.declare(&stmt.name.0, Declaration::Item);
+        let ctx = stmt.fields.iter().try_fold(ctx, |ctx, field| match field {
+            ast::ClassField::Method(f)
+            | ast::ClassField::StaticMethod(f)
+            | ast::ClassField::Getter(f)
+            | ast::ClassField::Setter(f) => self.visit_method(ctx, stmt, f),
+            ast::ClassField::Let(f) | ast::ClassField::StaticLet(f) => match &f.value {
+                Some(value) => self.visit_expression(ctx, value),
+                None => Ok(ctx),
+            },
+        })?;
+        self.visit_decorators(ctx, &stmt.decorators)
+    }
+
+    fn visit_enum(&self, ctx: Builder, stmt: &ast::Enum) -> Result {
+        self.declare(&stmt.name.0, Declaration::Item);
+        Ok(ctx)
+    }
+
+    fn visit_fn(&self, ctx: Builder, stmt: &ast::Function) -> Result {
+        // Emitted as `local function`, so the body sees its own name.
+        self.declare(&stmt.name.0, Declaration::Item);
+        let body = ast::ScriptOrExpression::Script(stmt.body.clone());
+        let ctx = self.within(&stmt.name.0, || {
+            self.visit_function(ctx, &[], &stmt.arguments, &body)
+        })?;
+        self.visit_decorators(ctx, &stmt.decorators)
+    }
+
+    fn visit_assignment(&self, ctx: Builder, stmt: &ast::Assignment) -> Result {
+        let ctx = self.visit_expression(ctx, &stmt.value)?;
+        match (&stmt.target.head, stmt.target.tail.is_empty()) {
+            (ast::Expression::Identifier(id), true) if stmt.extra.is_none() => {
+                if !self.is_declared(&id.0) && !self.allowed.contains(&id.0) {
+                    self.report(
+                        Rule::UndeclaredAssignment,
+                        format!(
+                            "`{}` is assigned without a declaration, creating a global",
+                            id.0
+                        ),
+                    );
+                    self.globals.borrow_mut().insert(id.0.clone());
+                }
+                Ok(ctx)
+            }
+            _ => {
+                if let Some(op) = stmt.extra.as_ref().filter(|op| !is_native_operator(&op.0)) {
+                    let name = generate_operator_function_name(op.0.clone());
+                    self.read(&name, format!("Operator `{}`", op.0));
+                }
+                self.visit_reference(ctx, &stmt.target)
+            }
+        }
+    }
+
+    fn visit_declaration(&self, ctx: Builder, stmt: &ast::Let) -> Result {
+        let ctx = match &stmt.value {
+            Some(value) => self.visit_expression(ctx, value)?,
+            None => ctx,
+        };
+        let ctx = match &stmt.else_branch {
+            Some(else_branch) => self.visit_block(ctx, else_branch)?,
+            None => ctx,
+        };
+        // Exported names are read by the importing modules.
+        let declaration = if stmt.public {
+            Declaration::Item
+        } else {
+            Declaration::Variable
+        };
+        self.visit_target(ctx, &stmt.target, declaration)
+    }
+
+    fn visit_expression_statement(&self, ctx: Builder, stmt: &ast::Expression) -> Result {
+        self.visit_expression(ctx, stmt)
+    }
+
+    fn visit_use_statement(&self, ctx: Builder, stmt: &ast::UseStatement) -> Result {
+        match &stmt.expanded {
+            Some(expanded) => self.visit_destructuring(ctx, expanded, Declaration::Variable),
+            None => {
+                self.declare(stmt.module.last().unwrap(), Declaration::Variable);
+                Ok(ctx)
+            }
+        }
+    }
+
+    fn visit_extern_block(&self, ctx: Builder, stmt: &ast::Extern) -> Result {
+        if stmt.id == "Lua" {
+            let tokens = lua_tokens(&stmt.src);
+            // The Lua code may read any binding in scope.
+            tokens.iter().for_each(|token| {
+                self.mark_read(token);
+            });
+            lua_declarations(&tokens)
+                .iter()
+                .for_each(|name| self.declare(name, Declaration::Implicit));
+        }
+        Ok(ctx)
+    }
+
+    fn visit_for(&self, ctx: Builder, expr: &ast::For) -> Result {
+        let ctx = self.visit_expression(ctx, &expr.target)?;
+        self.scoped(ctx, |ctx| {
+            let ctx = self.visit_target(ctx, &expr.handler, Declaration::Variable)?;
+            self.visit_body(ctx, "for", &expr.body)
+        })
+    }
+
+    fn visit_while(&self, ctx: Builder, expr: &ast::While) -> Result {
+        self.scoped(ctx, |ctx| {
+            let ctx = match &expr.condition {
+                ast::ExpressionOrLet::Expression(e) => self.visit_expression(ctx, e)?,
+                ast::ExpressionOrLet::Let(e) => self.visit_declaration(ctx, e)?,
+            };
+            self.visit_body(ctx, "while", &expr.body)
+        })
+    }
+
+    fn visit_loop(&self, ctx: Builder, expr: &ast::Loop) -> Result {
+        self.visit_body(ctx, "loop", &expr.body)
+    }
+
+    fn visit_if(&self, ctx: Builder, expr: &ast::If) -> Result {
+        // `if a == () { a = b; }`
+        let fallback = match expr.body.statements.as_slice() {
+            [ast::Statement::Assignment(assignment)] if assignment.extra.is_none() => {
+                let target = ast::Expression::Reference(Box::new(assignment.target.clone()));
+                nil_compared(&expr.condition, "==").is_some_and(|a| same_reference(a, &target))
+            }
+            _ => false,
+        };
+        if fallback && expr.branches.is_empty() && expr.else_branch.is_none() {
+            self.report(
+                Rule::NilComparison,
+                "Comparison with `()` to assign a fallback, use `a = a ?? b;`".into(),
+            );
+        }
+        let ctx = self.visit_expression(ctx, &expr.condition)?;
+        let ctx = self.visit_body(ctx, "if", &expr.body)?;
+        let ctx = expr.branches.iter().try_fold(ctx, |ctx, (c, s)| {
+            let ctx = self.visit_expression(ctx, c)?;
+            self.visit_body(ctx, "else if", s)
+        })?;
+        match &expr.else_branch {
+            Some(else_branch) => self.visit_body(ctx, "else", else_branch),
+            None => Ok(ctx),
+        }
+    }
+
+    fn visit_if_expression(&self, ctx: Builder, expr: &ast::IfExpression) -> Result {
+        // `if a == () { b } else { a }` and `if a <> () { a } else { b }`
+        let value = |block: &ast::ValueBlock| match &block.value {
+            Some(value) if block.body.statements.is_empty() => Some(value.clone()),
+            _ => None,
+        };
+        let fallback = match (value(&expr.body), expr.else_branch.as_ref().and_then(value)) {
+            (Some(then), Some(otherwise)) if expr.branches.is_empty() => {
+                nil_compared(&expr.condition, "==").is_some_and(|a| same_reference(a, &otherwise))
+                    || nil_compared(&expr.condition, "<>").is_some_and(|a| same_reference(a, &then))
+            }
+            _ => false,
+        };
+        if fallback {
+            self.report(
+                Rule::NilComparison,
+                "Comparison with `()` to pick a fallback, use `a ?? b`".into(),
+            );
+        }
+        let ctx = self.visit_expression(ctx, &expr.condition)?;
+        let ctx = self.visit_value_block(ctx, &expr.body)?;
+        let ctx = expr.branches.iter().try_fold(ctx, |ctx, (c, b)| {
+            let ctx = self.visit_expression(ctx, c)?;
+            self.visit_value_block(ctx, b)
+        })?;
+        match &expr.else_branch {
+            Some(else_branch) => self.visit_value_block(ctx, else_branch),
+            None => Ok(ctx),
+        }
+    }
+
+    // The branch patterns bind names the emitter does not handle yet, so
+    // only the matched value is checked.
+    fn visit_match(&self, ctx: Builder, expr: &ast::Match) -> Result {
+        self.visit_expression(ctx, &expr.target)
+    }
+
+    fn visit_try(&self, ctx: Builder, stmt: &ast::Try) -> Result {
+        let ctx = self.visit_body(ctx, "try", &stmt.body)?;
+        let ctx = match &stmt.catch {
+            Some(catch) => self.scoped(ctx, |ctx| {
+                for name in catch.error.iter().chain(&catch.traceback) {
+                    self.declare(&name.0, Declaration::Item);
+                }
+                self.visit_body(ctx, "catch", &catch.body)
+            })?,
+            None => ctx,
+        };
+        match &stmt.finally {
+            Some(finally) => self.visit_body(ctx, "finally", finally),
+            None => Ok(ctx),
+        }
+    }
+
+    fn visit_defer(&self, ctx: Builder, stmt: &ast::Defer) -> Result {
+        self.visit_expression(ctx, &stmt.value)
+    }
+
+    fn visit_block_expression(&self, ctx: Builder, expr: &ast::Do) -> Result {
+        self.visit_body(ctx, "do", &expr.body)
+    }
+
+    fn visit_script(&self, ctx: Builder, script: &Script) -> Result {
+        self.visit_block(ctx, script)
+    }
+
+    fn visit_macro_decorator(&self, ctx: Builder, stmt: &ast::MacroDecorator) -> Result {
+        let allowed = allowed_rules(&stmt.macros);
+        let count = self.allowed_rules.borrow().len();
+        self.allowed_rules.borrow_mut().extend(allowed);
+        let result = self.visit_statement(ctx, &stmt.target);
+        self.allowed_rules.borrow_mut().truncate(count);
+        result
+    }
+
+    fn visit_macro_call(&self, ctx: Builder, expr: &ast::MacroCallExpression) -> Result {
+        match &expr.arguments {
+            Some(arguments) => arguments
+                .iter()
+                .try_fold(ctx, |ctx, arg| self.visit_expression(ctx, arg)),
+            None => Ok(ctx),
+        }
+    }
+
+    fn visit_block(&self, ctx: Builder, script: &Script) -> Result {
+        self.scoped(ctx, |ctx| self.visit_statements(ctx, script))
+    }
+}
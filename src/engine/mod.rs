@@ -0,0 +1,298 @@
+pub mod resolver;
+pub mod sandbox;
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rlua::{FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti, Value};
+
+use crate::{
+    code::{ast_visitor::Visitor, builder::Builder, info::InputFileInfo},
+    errors::report_error,
+    lua::{helpers::generate_module_chunk, visitor::LuaEmitter},
+    parser::Script,
+    runtime::{precompile_std, RuntimeError},
+};
+
+pub use resolver::{
+    FileSystemResolver, MemoryResolver, ModuleResolver, ResolvedModule, UnresolvedModule,
+};
+pub use sandbox::Sandbox;
+
+/// Compiles a single Saturnus source into Lua code.
+fn compile_source(path: PathBuf, indent: &str, src: &str) -> Result<String, RuntimeError> {
+    let script = Script::parse(src).map_err(RuntimeError::ParseError)?;
+    let compiler = LuaEmitter::new(InputFileInfo { full_path: path });
+    let code = compiler
+        .visit_script(Builder::new(indent), &script)
+        .map_err(RuntimeError::CompilationError)?
+        .collect();
+    Ok(code)
+}
+
+/// The slot of `package.searchers` taken by resolvers. Right after
+/// `package.preload`, so embedded modules win over files.
+const RESOLVER_SLOT: i64 = 2;
+
+/// Installs a package searcher for the modules found by the resolver, which
+/// are compiled when first required. It runs right after `package.preload`,
+/// before Lua falls back to its own file searchers.
+pub fn install_resolver<T>(ctx: rlua::Context, resolver: T, indent: &str) -> rlua::Result<()>
+where
+    T: ModuleResolver + 'static,
+{
+    let searcher = resolver_searcher(ctx, resolver, indent)?;
+    let insert: rlua::Function = ctx
+        .globals()
+        .get::<_, rlua::Table>("table")?
+        .get("insert")?;
+    insert.call::<_, ()>((package_searchers(ctx)?, RESOLVER_SLOT, searcher))
+}
+
+fn package_searchers(ctx: rlua::Context) -> rlua::Result<rlua::Table> {
+    ctx.globals()
+        .get::<_, rlua::Table>("package")?
+        .get("searchers")
+}
+
+/// A package searcher that compiles the modules found by the resolver.
+fn resolver_searcher<'lua, T>(
+    ctx: rlua::Context<'lua>,
+    resolver: T,
+    indent: &str,
+) -> rlua::Result<rlua::Function<'lua>>
+where
+    T: ModuleResolver + 'static,
+{
+    let resolver = Arc::new(resolver);
+    let indent = indent.to_owned();
+    ctx.create_function(move |ctx, name: String| {
+        let Some(module) = resolver.resolve(&name) else {
+            let reason = format!("\n\tno module '{name}' in Saturnus resolver");
+            return Ok(MultiValue::from_vec(vec![reason.to_lua(ctx)?]));
+        };
+        let code = compile_source(module.path.clone(), &indent, &module.source).map_err(|err| {
+            let path = module.path.to_string_lossy().to_string();
+            rlua::Error::RuntimeError(match err {
+                RuntimeError::ParseError(err) => report_error(path, module.source.clone(), err),
+                err => format!("Failed to compile {path}: {err:?}"),
+            })
+        })?;
+        let path = module.path.to_string_lossy().to_string();
+        let loader = ctx.load(&code).set_name(&path)?.into_function()?;
+        Ok(MultiValue::from_vec(vec![
+            Value::Function(loader),
+            path.to_lua(ctx)?,
+        ]))
+    })
+}
+
+/// Seconds since the Unix epoch. Unlike `os.time`, it has sub-second
+/// resolution, and unlike `os.clock` it keeps counting while the process waits.
+fn wall_clock() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+/// The `Task` scheduler class of the standard library.
+fn std_task(ctx: rlua::Context) -> rlua::Result<rlua::Table> {
+    let require: rlua::Function = ctx.globals().get("require")?;
+    require.call::<_, rlua::Table>("std")?.get("Task")
+}
+
+/// # Saturnus Engine
+///
+/// The supported way of hosting Saturnus inside a Rust application. The engine
+/// owns a Lua state with the Saturnus standard library preloaded, compiles
+/// scripts on demand (caching the generated code by source) and lets the host
+/// expose functions, userdata and globals to the scripts.
+///
+/// Example:
+/// ```rs
+/// let engine = Engine::new()?;
+/// engine.register_fn("add", |(a, b): (i64, i64)| Ok(a + b))?;
+/// engine.exec("fn twice(x) = add(x, x); double = twice;")?;
+/// let four: i64 = engine.call("double", 2)?;
+/// ```
+pub struct Engine {
+    lua: rlua::Lua,
+    indent: String,
+    path: PathBuf,
+    sandbox: Option<Sandbox>,
+    /// Whether a resolver took its searcher slot already, later ones replace it.
+    has_resolver: Cell<bool>,
+    /// Compiled code by script path and source, macros like `file!()`
+    /// depend on both.
+    pub(crate) cache: RefCell<HashMap<(PathBuf, md5::Digest), String>>,
+}
+
+impl Engine {
+    pub fn new() -> Result<Self, RuntimeError> {
+        Self::from_lua(rlua::Lua::new())
+    }
+
+    /// Builds an engine for untrusted scripts, every evaluation is bound to
+    /// the limits of the given sandbox.
+    pub fn sandboxed(sandbox: Sandbox) -> Result<Self, RuntimeError> {
+        let mut engine = Self::from_lua(sandbox.create_lua())?;
+        engine.sandbox = Some(sandbox);
+        Ok(engine)
+    }
+
+    /// Builds an engine on top of an already configured Lua state.
+    pub fn from_lua(lua: rlua::Lua) -> Result<Self, RuntimeError> {
+        let engine = Engine {
+            lua,
+            indent: "  ".into(),
+            path: PathBuf::from("main.saturn"),
+            sandbox: None,
+            has_resolver: Cell::new(false),
+            cache: RefCell::new(HashMap::new()),
+        };
+        let compiler = LuaEmitter::new(InputFileInfo {
+            full_path: PathBuf::from("std.saturn"),
+        });
+        let (std_src, _) = precompile_std(&compiler)?;
+        let std_chunk = generate_module_chunk(&"std".into(), &std_src);
+        engine.run_lua(|ctx| ctx.load(&std_chunk).set_name("std")?.exec())?;
+        engine.set_clock(wall_clock)?;
+        Ok(engine)
+    }
+
+    /// Path reported by `file!()` and in errors for scripts run through this
+    /// engine, `main.saturn` by default.
+    pub fn set_script_path<P: Into<PathBuf>>(&mut self, path: P) {
+        self.path = path.into();
+    }
+
+    /// Compiles the given source to Lua, reusing the previous output if the
+    /// same source was already compiled by this engine at the same path.
+    pub fn compile(&self, src: &str) -> Result<String, RuntimeError> {
+        let key = (self.path.clone(), md5::compute(src.as_bytes()));
+        if let Some(code) = self.cache.borrow().get(&key) {
+            return Ok(code.clone());
+        }
+        let code = compile_source(self.path.clone(), &self.indent, src)?;
+        self.cache.borrow_mut().insert(key, code.clone());
+        Ok(code)
+    }
+
+    /// Runs a script, discarding whatever it returns.
+    pub fn exec(&self, src: &str) -> Result<(), RuntimeError> {
+        let code = self.compile(src)?;
+        self.run_lua(|ctx| ctx.load(&code).exec())
+    }
+
+    /// Runs a script and converts its return value into `R`.
+    pub fn eval<R>(&self, src: &str) -> Result<R, RuntimeError>
+    where
+        R: for<'lua> FromLuaMulti<'lua>,
+    {
+        let code = self.compile(src)?;
+        self.run_lua(|ctx| ctx.load(&code).call(()))
+    }
+
+    /// Calls the global function `name`, converting the arguments and the
+    /// returned values.
+    pub fn call<A, R>(&self, name: &str, args: A) -> Result<R, RuntimeError>
+    where
+        A: for<'lua> ToLuaMulti<'lua>,
+        R: for<'lua> FromLuaMulti<'lua>,
+    {
+        self.run_lua(|ctx| {
+            let func: rlua::Function = ctx.globals().get(name)?;
+            func.call(args)
+        })
+    }
+
+    /// Exposes a Rust function to the scripts as the global `name`.
+    pub fn register_fn<A, R, F>(&self, name: &str, func: F) -> Result<(), RuntimeError>
+    where
+        A: for<'lua> FromLuaMulti<'lua>,
+        R: for<'lua> ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(A) -> rlua::Result<R>,
+    {
+        self.run_lua(|ctx| {
+            let func = ctx.create_function(move |_, args: A| func(args))?;
+            ctx.globals().set(name, func)
+        })
+    }
+
+    /// Sets the global `name`. Any `rlua::UserData` can be passed here as well,
+    /// which is how host objects are handed to the scripts.
+    pub fn set_global<V>(&self, name: &str, value: V) -> Result<(), RuntimeError>
+    where
+        V: for<'lua> ToLua<'lua>,
+    {
+        self.run_lua(|ctx| ctx.globals().set(name, value))
+    }
+
+    pub fn get_global<V>(&self, name: &str) -> Result<V, RuntimeError>
+    where
+        V: for<'lua> FromLua<'lua>,
+    {
+        self.run_lua(|ctx| ctx.globals().get(name))
+    }
+
+    /// Steps each task scheduled with `Task.spawn` once, returning how many
+    /// are still pending. Hosts call this from their own event loop.
+    pub fn tick(&self) -> Result<usize, RuntimeError> {
+        self.run_lua(|ctx| std_task(ctx)?.get::<_, rlua::Function>("tick")?.call(()))
+    }
+
+    /// Replaces the clock, in seconds, that `Task.sleep` waits on. Engines
+    /// start with the system's wall clock.
+    pub fn set_clock<F>(&self, clock: F) -> Result<(), RuntimeError>
+    where
+        F: 'static + Send + Fn() -> f64,
+    {
+        self.run_lua(|ctx| {
+            let clock = ctx.create_function(move |_, ()| Ok(clock()))?;
+            std_task(ctx)?.set("clock", clock)
+        })
+    }
+
+    /// Installs a resolver used by `use` statements, see [`install_resolver`].
+    /// It replaces the resolver set before, if any.
+    pub fn set_resolver<T>(&self, resolver: T) -> Result<(), RuntimeError>
+    where
+        T: ModuleResolver + 'static,
+    {
+        let indent = self.indent.clone();
+        self.run_lua(|ctx| {
+            if !self.has_resolver.replace(true) {
+                return install_resolver(ctx, resolver, &indent);
+            }
+            let searcher = resolver_searcher(ctx, resolver, &indent)?;
+            package_searchers(ctx)?.set(RESOLVER_SLOT, searcher)
+        })
+    }
+
+    /// Gives raw access to the underlying Lua state, for anything the engine
+    /// does not cover.
+    pub fn context<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(rlua::Context) -> R,
+    {
+        self.lua.context(f)
+    }
+
+    fn run_lua<F, R>(&self, f: F) -> Result<R, RuntimeError>
+    where
+        F: FnOnce(rlua::Context) -> rlua::Result<R>,
+    {
+        match &self.sandbox {
+            Some(sandbox) => {
+                sandbox.guard(&self.lua);
+                self.lua.context(f).map_err(|err| sandbox.map_error(err))
+            }
+            None => self.lua.context(f).map_err(RuntimeError::EvaluationError),
+        }
+    }
+}
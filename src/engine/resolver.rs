@@ -0,0 +1,121 @@
+use std::{collections::HashMap, fmt, path::PathBuf};
+
+/// A Saturnus module located by a [`ModuleResolver`].
+#[derive(Debug, Clone)]
+pub struct ResolvedModule {
+    /// Path reported to macros such as `file!()` and in error messages.
+    pub path: PathBuf,
+    /// The Saturnus source code of the module.
+    pub source: String,
+}
+
+/// # Module resolver
+///
+/// Maps the dotted path of a `use` statement (Eg: `use a.b.c;` asks for
+/// `"a.b.c"`) to the Saturnus source backing it. Returning `None` lets Lua
+/// continue with the rest of its package searchers.
+pub trait ModuleResolver: Send + Sync {
+    fn resolve(&self, module: &str) -> Option<ResolvedModule>;
+}
+
+/// Looks up modules as `.saturn` files below a set of root folders, so
+/// `use a.b;` maps to `<root>/a/b.saturn` or `<root>/a/b/init.saturn`.
+#[derive(Clone)]
+pub struct FileSystemResolver {
+    pub roots: Vec<PathBuf>,
+}
+impl FileSystemResolver {
+    pub fn new<I, P>(roots: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        FileSystemResolver {
+            roots: roots.into_iter().map(Into::into).collect(),
+        }
+    }
+    /// The files that may back the module, in lookup order.
+    pub fn candidates(&self, module: &str) -> Vec<PathBuf> {
+        let relative = module.split('.').collect::<PathBuf>();
+        self.roots
+            .iter()
+            .flat_map(|root| {
+                [
+                    root.join(&relative).with_extension("saturn"),
+                    root.join(&relative).join("init.saturn"),
+                ]
+            })
+            .collect()
+    }
+    /// The file backing the module, as Saturnus source or as an already
+    /// compiled (or native) Lua file next to the Saturnus candidates.
+    pub fn locate(&self, module: &str) -> Result<PathBuf, UnresolvedModule> {
+        let searched = self
+            .candidates(module)
+            .into_iter()
+            .flat_map(|path| [path.with_extension("lua"), path].into_iter().rev())
+            .collect::<Vec<_>>();
+        match searched.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(UnresolvedModule {
+                module: module.to_owned(),
+                searched,
+            }),
+        }
+    }
+}
+impl ModuleResolver for FileSystemResolver {
+    fn resolve(&self, module: &str) -> Option<ResolvedModule> {
+        self.candidates(module).into_iter().find_map(|path| {
+            std::fs::read_to_string(&path)
+                .ok()
+                .map(|source| ResolvedModule { path, source })
+        })
+    }
+}
+
+/// A `use` statement naming a module that no root provides.
+#[derive(Debug)]
+pub struct UnresolvedModule {
+    pub module: String,
+    /// Every path that was tried, in lookup order.
+    pub searched: Vec<PathBuf>,
+}
+impl fmt::Display for UnresolvedModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Module {} not found, searched:", self.module)?;
+        for path in self.searched.iter() {
+            write!(f, "\n  {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for UnresolvedModule {}
+
+/// Serves modules from sources held in memory, useful when scripts are
+/// shipped inside the host application or loaded from a database.
+#[derive(Default)]
+pub struct MemoryResolver {
+    pub modules: HashMap<String, String>,
+}
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn with_module<N, S>(mut self, name: N, source: S) -> Self
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self.modules.insert(name.into(), source.into());
+        self
+    }
+}
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, module: &str) -> Option<ResolvedModule> {
+        self.modules.get(module).map(|source| ResolvedModule {
+            path: PathBuf::from(format!("{}.saturn", module.replace('.', "/"))),
+            source: source.clone(),
+        })
+    }
+}
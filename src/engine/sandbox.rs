@@ -0,0 +1,235 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use rlua::{HookTriggers, StdLib};
+
+use crate::runtime::RuntimeError;
+
+/// How many VM instructions run between two checks of the sandbox limits.
+const HOOK_GRANULARITY: u32 = 1000;
+
+/// Globals removed from sandboxed states, even if their library is allowed.
+const BLOCKED_GLOBALS: [&str; 4] = ["io", "load", "loadfile", "dofile"];
+const BLOCKED_OS_FIELDS: [&str; 6] = ["execute", "exit", "remove", "rename", "tmpname", "getenv"];
+
+/// Raised from inside the Lua hook when a limit is exceeded, and translated
+/// back to a [`RuntimeError`] once the evaluation unwinds.
+#[derive(Debug)]
+enum Violation {
+    Instructions(u64),
+    Timeout(Duration),
+}
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Instructions(limit) => {
+                write!(f, "Sandbox instruction limit of {limit} exceeded")
+            }
+            Violation::Timeout(limit) => write!(f, "Sandbox timeout of {limit:?} exceeded"),
+        }
+    }
+}
+impl std::error::Error for Violation {}
+
+/// # Sandbox
+///
+/// Restricts what untrusted scripts can do: only the whitelisted standard
+/// libraries are opened, the file, process and dynamic code loading functions
+/// are removed, and evaluations are bounded by an instruction budget, a memory
+/// limit and a wall-clock timeout.
+///
+/// Example:
+/// ```rs
+/// let sandbox = Sandbox::new()
+///     .instruction_limit(1_000_000)
+///     .memory_limit(16 * 1024 * 1024)
+///     .timeout(Duration::from_millis(50));
+/// let engine = Engine::sandboxed(sandbox)?;
+/// ```
+#[derive(Clone)]
+pub struct Sandbox {
+    libs: StdLib,
+    instruction_limit: Option<u64>,
+    memory_limit: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+/// Library names shown by the `Debug` output, as `StdLib` does not implement
+/// `Debug` in newer rlua releases.
+const LIB_NAMES: [(StdLib, &str); 10] = [
+    (StdLib::BASE, "base"),
+    (StdLib::COROUTINE, "coroutine"),
+    (StdLib::TABLE, "table"),
+    (StdLib::IO, "io"),
+    (StdLib::OS, "os"),
+    (StdLib::STRING, "string"),
+    (StdLib::UTF8, "utf8"),
+    (StdLib::MATH, "math"),
+    (StdLib::PACKAGE, "package"),
+    (StdLib::DEBUG, "debug"),
+];
+
+impl fmt::Debug for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let libs: Vec<&str> = LIB_NAMES
+            .iter()
+            .filter(|(lib, _)| self.libs.contains(*lib))
+            .map(|(_, name)| *name)
+            .collect();
+        f.debug_struct("Sandbox")
+            .field("libs", &libs)
+            .field("instruction_limit", &self.instruction_limit)
+            .field("memory_limit", &self.memory_limit)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox {
+            libs: StdLib::BASE
+                | StdLib::COROUTINE
+                | StdLib::TABLE
+                | StdLib::STRING
+                | StdLib::UTF8
+                | StdLib::MATH
+                | StdLib::PACKAGE,
+            instruction_limit: None,
+            memory_limit: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Replaces the whitelist of Lua standard libraries. `package` is needed
+    /// for `use` statements, `debug` is never opened.
+    pub fn libs(self, libs: StdLib) -> Self {
+        Sandbox {
+            libs: libs - StdLib::DEBUG,
+            ..self
+        }
+    }
+    /// Maximum amount of Lua VM instructions a single evaluation may run.
+    pub fn instruction_limit(self, limit: u64) -> Self {
+        Sandbox {
+            instruction_limit: Some(limit),
+            ..self
+        }
+    }
+    /// Maximum size in bytes of the whole Lua heap, standard library included.
+    pub fn memory_limit(self, limit: usize) -> Self {
+        Sandbox {
+            memory_limit: Some(limit),
+            ..self
+        }
+    }
+    /// Maximum wall-clock time a single evaluation may take.
+    pub fn timeout(self, limit: Duration) -> Self {
+        Sandbox {
+            timeout: Some(limit),
+            ..self
+        }
+    }
+
+    /// Creates a Lua state with only the allowed libraries, and the unsafe
+    /// globals stripped away.
+    pub fn create_lua(&self) -> rlua::Lua {
+        let lua = rlua::Lua::new_with(self.libs);
+        lua.context(|ctx| -> rlua::Result<()> {
+            let globals = ctx.globals();
+            for name in BLOCKED_GLOBALS {
+                globals.set(name, rlua::Nil)?;
+            }
+            if let Ok(os) = globals.get::<_, rlua::Table>("os") {
+                for name in BLOCKED_OS_FIELDS {
+                    os.set(name, rlua::Nil)?;
+                }
+            }
+            if let Ok(package) = globals.get::<_, rlua::Table>("package") {
+                // Loaded libraries would be handed back by `require` too.
+                let loaded: rlua::Table = package.get("loaded")?;
+                for name in BLOCKED_GLOBALS {
+                    loaded.set(name, rlua::Nil)?;
+                }
+                if let Ok(os) = loaded.get::<_, rlua::Table>("os") {
+                    for name in BLOCKED_OS_FIELDS {
+                        os.set(name, rlua::Nil)?;
+                    }
+                }
+                // Only package.preload (and resolvers added later) may serve modules.
+                let searchers: rlua::Table = package.get("searchers")?;
+                let preload: rlua::Value = searchers.get(1)?;
+                package.set("searchers", ctx.create_sequence_from([preload])?)?;
+                package.set("path", "")?;
+                package.set("cpath", "")?;
+            }
+            Ok(())
+        })
+        .expect("Could not strip the sandboxed Lua globals");
+        lua.set_memory_limit(self.memory_limit);
+        lua
+    }
+
+    /// Installs the limit checks for a new evaluation, resetting the
+    /// instruction budget and the timeout clock.
+    pub fn guard(&self, lua: &rlua::Lua) {
+        if self.instruction_limit.is_none() && self.timeout.is_none() {
+            return;
+        }
+        let instruction_limit = self.instruction_limit;
+        let timeout = self.timeout;
+        let start = Instant::now();
+        let mut executed = 0_u64;
+        lua.set_hook(
+            HookTriggers {
+                every_nth_instruction: Some(HOOK_GRANULARITY),
+                ..Default::default()
+            },
+            move |_, _| {
+                executed += HOOK_GRANULARITY as u64;
+                if let Some(limit) = instruction_limit {
+                    if executed > limit {
+                        return Err(rlua::Error::external(Violation::Instructions(limit)));
+                    }
+                }
+                if let Some(limit) = timeout {
+                    if start.elapsed() > limit {
+                        return Err(rlua::Error::external(Violation::Timeout(limit)));
+                    }
+                }
+                Ok(())
+            },
+        );
+    }
+
+    /// Translates errors caused by a sandbox limit into their typed
+    /// [`RuntimeError`] counterpart.
+    pub fn map_error(&self, err: rlua::Error) -> RuntimeError {
+        match (root_cause(&err), self.memory_limit) {
+            (rlua::Error::MemoryError(_), Some(limit)) => RuntimeError::MemoryLimitExceeded(limit),
+            (rlua::Error::ExternalError(cause), _) => match cause.downcast_ref::<Violation>() {
+                Some(Violation::Instructions(limit)) => {
+                    RuntimeError::InstructionLimitExceeded(*limit)
+                }
+                Some(Violation::Timeout(limit)) => RuntimeError::TimeoutExceeded(*limit),
+                None => RuntimeError::EvaluationError(err),
+            },
+            _ => RuntimeError::EvaluationError(err),
+        }
+    }
+}
+
+/// Errors raised inside Rust callbacks come back wrapped, dig them out.
+fn root_cause(err: &rlua::Error) -> &rlua::Error {
+    match err {
+        rlua::Error::CallbackError { cause, .. } => root_cause(cause),
+        err => err,
+    }
+}
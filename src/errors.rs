@@ -12,7 +12,7 @@ pub fn report_error(
     let ep = err
         .expected
         .tokens()
-        .map(|x| String::from(x))
+        .map(String::from)
         .reduce(|a, b| format!("{}, {}", a, b));
     result += format!("At {}:{}:{}", file, line, col).as_str();
     if let Some(ep) = ep {
@@ -32,9 +32,10 @@ pub fn report_error(
             result += format!("{} {} {}\n", numeric, divider, line_str).as_str();
             if line == pos {
                 let ted = line_str.len();
-                let ted = if ted > col { ted - col } else { 0 };
+                let ted = ted.saturating_sub(col);
                 let premark = style("     |").red().bold();
-                let spanner = format!(" {:2$}{:^<3$}", " ", "^", col - 2, ted);
+                let padding = " ".repeat(col.saturating_sub(1));
+                let spanner = format!("{padding}{:^<ted$}", "^");
                 let spanner = style(spanner).red();
                 let here = style("here").red();
                 result += format!("{} {} {here}\n", premark, spanner).as_str();
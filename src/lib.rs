@@ -0,0 +1,17 @@
+//! # Saturnus
+//!
+//! Library side of the Saturnus compiler. Besides the `saturnus` binary, the
+//! parser, code generators and the [`Engine`] are exposed so the language can
+//! be embedded inside other Rust applications.
+
+pub mod code;
+pub mod engine;
+pub mod errors;
+pub mod lint;
+pub mod lua;
+pub mod parser;
+pub mod runtime;
+#[cfg(test)]
+mod tests;
+
+pub use engine::Engine;
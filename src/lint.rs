@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use crate::{
+    code::{ast_visitor::VisitError, scope_analysis::ScopeAnalyzer},
+    parser::Script,
+};
+
+/// The checks run by `saturnus lint`. Each one can be silenced for a single
+/// statement with `#[allow(rule_name)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Reading a name that no scope declares.
+    UndeclaredNames,
+    /// A `let`, `for` or `use` binding that is never read.
+    UnusedVariables,
+    /// Declaring a name that is already in scope.
+    Shadowing,
+    /// Statements following a `return` in the same block.
+    UnreachableCode,
+    /// Assigning to a name that was never declared, creating a Lua global.
+    UndeclaredAssignment,
+    /// `x == ()`, where `??` expresses the fallback.
+    NilComparison,
+    /// Conditional, loop and `try` blocks without statements.
+    EmptyBlocks,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::UndeclaredNames,
+        Rule::UnusedVariables,
+        Rule::Shadowing,
+        Rule::UnreachableCode,
+        Rule::UndeclaredAssignment,
+        Rule::NilComparison,
+        Rule::EmptyBlocks,
+    ];
+
+    /// The name used in Janus.toml, the command line and `#[allow(...)]`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UndeclaredNames => "undeclared_names",
+            Rule::UnusedVariables => "unused_variables",
+            Rule::Shadowing => "shadowing",
+            Rule::UnreachableCode => "unreachable_code",
+            Rule::UndeclaredAssignment => "undeclared_assignment",
+            Rule::NilComparison => "nil_comparison",
+            Rule::EmptyBlocks => "empty_blocks",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+
+    /// Shadowing is idiomatic (`let x = x + 1;`), so it is opt-in.
+    pub fn default_level(&self) -> Level {
+        match self {
+            Rule::Shadowing => Level::Allow,
+            _ => Level::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            level => Err(format!(
+                "Unknown lint level '{level}', expected allow, warn or deny"
+            )),
+        }
+    }
+}
+
+/// A finding of the analysis, before the configured level is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub message: String,
+    /// Where the statement it was found in starts, as a byte offset.
+    pub position: usize,
+}
+
+/// A finding reported at the level configured for its rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub level: Level,
+    pub message: String,
+    /// Where the statement it was found in starts, as a byte offset.
+    pub position: usize,
+}
+impl Lint {
+    /// The line and column of the lint in the linted source, both from 1.
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let before = src.get(..self.position).unwrap_or(src);
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+impl Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Deny => "error",
+            _ => "warning",
+        };
+        write!(f, "{level}[{}]: {}", self.rule.name(), self.message)
+    }
+}
+
+/// # Lint configuration
+///
+/// The level of each rule, and the globals provided by the host. Janus reads
+/// it from the `[lint]` table of Janus.toml.
+///
+/// Example:
+/// ```rs
+/// let config = LintConfig::new()
+///     .level(Rule::Shadowing, Level::Warn)
+///     .level(Rule::UnusedVariables, Level::Deny)
+///     .allow_global("love");
+/// let lints = lint(&script, &config)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    levels: HashMap<Rule, Level>,
+    globals: Vec<String>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            levels: Rule::ALL
+                .into_iter()
+                .map(|rule| (rule, rule.default_level()))
+                .collect(),
+            globals: vec![],
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn level(mut self, rule: Rule, level: Level) -> Self {
+        self.levels.insert(rule, level);
+        self
+    }
+
+    /// Allows reading and assigning a global provided by the host.
+    pub fn allow_global<N: Into<String>>(mut self, name: N) -> Self {
+        self.globals.push(name.into());
+        self
+    }
+
+    pub fn level_of(&self, rule: Rule) -> Level {
+        self.levels
+            .get(&rule)
+            .copied()
+            .unwrap_or(rule.default_level())
+    }
+}
+
+/// Runs every rule over the script, leaving out the allowed ones.
+pub fn lint(script: &Script, config: &LintConfig) -> Result<Vec<Lint>, VisitError> {
+    let findings = ScopeAnalyzer::new()
+        .allow_globals(config.globals.iter().cloned())
+        .findings(script)?;
+    let lints = findings
+        .into_iter()
+        .map(|finding| Lint {
+            rule: finding.rule,
+            level: config.level_of(finding.rule),
+            message: finding.message,
+            position: finding.position,
+        })
+        .filter(|lint| lint.level != Level::Allow)
+        .collect();
+    Ok(lints)
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use crate::{
+    code::{
+        ast_visitor::{VisitError, Visitor},
+        builder::Builder,
+        info::InputFileInfo,
+    },
+    engine::{FileSystemResolver, UnresolvedModule},
+    errors::report_error,
+    lua::{helpers::generate_module_chunk, visitor::LuaEmitter},
+    parser::Script,
+    runtime::{RuntimeError, StdUsage},
+};
+
+/// # Bundler
+///
+/// Follows the `use` statements of a compiled entry script, and compiles each
+/// module it reaches (once) into a `package.preload` chunk, so the program
+/// runs from a single Lua file. Modules that no `use` statement reaches are
+/// left out. The std library is handled by the caller as usual, but what the
+/// modules import from it is merged into the entry's `std_usage`.
+///
+/// Example:
+/// ```rs
+/// let compiler = LuaEmitter::new(info).with_resolver(resolver.clone());
+/// let main = compiler.visit_script(Builder::new("  "), &script)?.collect();
+/// let modules = Bundler::new(resolver, "  ").bundle(&compiler)?;
+/// let output = format!("{modules}\n{main}");
+/// ```
+pub struct Bundler {
+    resolver: FileSystemResolver,
+    indent: String,
+    visited: HashSet<String>,
+    chunks: Vec<String>,
+    std_usage: StdUsage,
+    /// Follows the entry's choice on modules that no root provides.
+    allow_unresolved: bool,
+    unresolved_modules: Vec<UnresolvedModule>,
+}
+
+impl Bundler {
+    pub fn new<T>(resolver: FileSystemResolver, indent: T) -> Self
+    where
+        T: Into<String>,
+    {
+        Bundler {
+            resolver,
+            indent: indent.into(),
+            visited: HashSet::new(),
+            chunks: vec![],
+            std_usage: StdUsage::default(),
+            allow_unresolved: false,
+            unresolved_modules: vec![],
+        }
+    }
+
+    /// The chunks of every module used by the entry, dependencies first.
+    pub fn bundle(mut self, entry: &LuaEmitter) -> Result<String, RuntimeError> {
+        self.allow_unresolved = entry.allow_unresolved;
+        for module in entry.used_modules.borrow().iter() {
+            self.add(module)?;
+        }
+        entry.std_usage.borrow_mut().merge(&self.std_usage);
+        entry
+            .unresolved_modules
+            .borrow_mut()
+            .append(&mut self.unresolved_modules);
+        Ok(self.chunks.join("\n"))
+    }
+
+    fn add(&mut self, module: &str) -> Result<(), RuntimeError> {
+        if !self.visited.insert(module.to_owned()) {
+            return Ok(());
+        }
+        let fail =
+            |err: Box<dyn std::error::Error>| RuntimeError::CompilationError(VisitError(err));
+        let path = self
+            .resolver
+            .locate(module)
+            .map_err(|err| fail(Box::new(err)))?;
+        let source = std::fs::read_to_string(&path).map_err(|err| fail(Box::new(err)))?;
+        // Lua modules are bundled as they are, and may require any of std.
+        let code = if path.extension().is_some_and(|ext| ext == "lua") {
+            self.std_usage = StdUsage::All;
+            source
+        } else {
+            let script = Script::parse(&source).map_err(|err| {
+                fail(report_error(path.to_string_lossy().to_string(), source.clone(), err).into())
+            })?;
+            let compiler = LuaEmitter::new(InputFileInfo {
+                full_path: path.clone(),
+            })
+            .with_resolver(self.resolver.clone())
+            .with_unresolved_allowed(self.allow_unresolved);
+            let code = compiler
+                .visit_script(Builder::new(self.indent.clone()), &script)
+                .map_err(RuntimeError::CompilationError)?
+                .collect();
+            self.std_usage.merge(&compiler.std_usage.borrow());
+            self.unresolved_modules
+                .append(&mut compiler.unresolved_modules.borrow_mut());
+            for dependency in compiler.used_modules.borrow().iter() {
+                self.add(dependency)?;
+            }
+            code
+        };
+        self.chunks
+            .push(generate_module_chunk(&module.to_owned(), &code));
+        Ok(())
+    }
+}
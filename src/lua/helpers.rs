@@ -1,6 +1,21 @@
+/// The local holding the loader of a module, escaped so that different
+/// module names never share it (Eg: `a.b` and `a_b`).
+fn module_loader_name(name: &str) -> String {
+    let escaped = name
+        .chars()
+        .map(|c| match c {
+            '.' => "__".to_owned(),
+            '_' => "_u".to_owned(),
+            c if c.is_ascii_alphanumeric() => c.to_string(),
+            c => format!("_x{:x}_", c as u32),
+        })
+        .collect::<String>();
+    format!("__saturnus_module_{escaped}")
+}
+
 /// Generates the given source as a virtual module, used by Lua target.
 pub fn generate_module_chunk(name: &String, source: &String) -> String {
-    let tmp = format!("__saturnus_module_{name}");
+    let tmp = module_loader_name(name);
     format!(
         "local {tmp} = function()
   {source}
@@ -12,3 +27,53 @@ else
 end"
     )
 }
+
+/// Quotes the given bytes as a Lua string literal. Multi-line text is emitted
+/// as a long bracket, so the generated code keeps its line breaks.
+pub fn quote_string(value: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(value) {
+        if text.contains('\n') && !text.contains('\r') {
+            return long_bracket(text);
+        }
+    }
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                // Padded, so a following digit can't be taken as part of the code.
+                c if c.is_ascii_control() => out.push_str(&format!("\\{:03}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        // Bytes that aren't UTF-8 can only be written as escapes.
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\{:03}", byte));
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Wraps the text in a Lua long bracket (`[==[ ... ]==]`), using the lowest
+/// level whose closing bracket does not occur in the text.
+pub fn long_bracket(value: &str) -> String {
+    // The trailing bracket catches text ending in a partial closing bracket.
+    let probe = format!("{value}]");
+    let level = (0..)
+        .map(|n| "=".repeat(n))
+        .find(|level| !probe.contains(&format!("]{level}]")))
+        .unwrap();
+    // Lua skips a line break right after the opening bracket.
+    let lead = if value.starts_with('\n') || value.starts_with('\r') {
+        "\n"
+    } else {
+        ""
+    };
+    format!("[{level}[{lead}{value}]{level}]")
+}
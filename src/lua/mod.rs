@@ -1,2 +1,3 @@
+pub mod bundle;
 pub mod helpers;
 pub mod visitor;
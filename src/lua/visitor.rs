@@ -1,3 +1,5 @@
+use std::cell::{Cell, RefCell};
+
 use crate::{
     code::{
         ast_visitor::{Result, VisitError, Visitor},
@@ -5,10 +7,14 @@ use crate::{
         info::InputFileInfo,
         macros::MacroHost,
     },
+    engine::{FileSystemResolver, UnresolvedModule},
+    lua::helpers::quote_string,
     parser::{
         ast::{self, Identifier},
-        helpers::generate_operator_function_name,
+        helpers::{generate_operator_function_name, operator_metamethod},
+        Script,
     },
+    runtime::StdUsage,
 };
 
 #[derive(Debug)]
@@ -24,42 +30,535 @@ impl std::error::Error for BadCode {
     }
 }
 
-fn escape_string(str: String) -> String {
-    return str.replace("\n", "\\n").replace("\r", "\\r");
+/// Steps the awaited task until it finishes, yielding to whoever steps the
+/// awaiting task meanwhile. Values that aren't tasks are returned as-is.
+const AWAIT_HELPER: &str = "\
+local function __saturnus_await__(task)
+  if type(task) ~= \"table\" or type(task.thread) ~= \"thread\" then return task; end
+  while not task.done do
+    local ok, result = coroutine.resume(task.thread);
+    if not ok then
+      task.done, task.error = true, result;
+    elseif coroutine.status(task.thread) == \"dead\" then
+      task.done, task.result = true, result;
+    else
+      coroutine.yield();
+    end
+  end
+  if task.error ~= nil then error(task.error, 0); end
+  return task.result;
+end";
+
+/// Runs protected blocks, packing their results. Errors are kept as raised,
+/// next to the traceback of where they were. Without the debug library, the
+/// traceback only lists the positions `error` reports for each level.
+const TRY_HELPER: &str = "\
+local function __saturnus_traceback__(e)
+  if debug ~= nil and debug.traceback ~= nil then
+    return { error = e, traceback = debug.traceback(nil, 2) };
+  end
+  local traceback = \"stack traceback:\";
+  for level = 3, 32 do
+    local _, where = pcall(error, \"\", level);
+    if where ~= \"\" then traceback = traceback .. \"\\n\\t\" .. where .. \"in ?\"; end
+  end
+  return { error = e, traceback = traceback };
+end
+local function __saturnus_try__(ok, ...)
+  return ok, { n = select(\"#\", ...), ... };
+end";
+
+/// The standard Lua libraries, which `use` can name without a file.
+const LUA_LIBRARIES: [&str; 9] = [
+    "coroutine",
+    "debug",
+    "io",
+    "math",
+    "os",
+    "package",
+    "string",
+    "table",
+    "utf8",
+];
+
+/// Lua's reserved words, which can't be used as a field name after a `.`
+/// nor as a method name after a `:`.
+const LUA_KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Indexes a field by name, quoted if it's a Lua keyword.
+fn field_access(name: &str) -> String {
+    if LUA_KEYWORDS.contains(&name) {
+        format!("[\"{name}\"]")
+    } else {
+        format!(".{name}")
+    }
+}
+
+/// A module can either return its own value or export `pub` items.
+#[derive(Debug)]
+struct ReturnWithExports;
+impl std::fmt::Display for ReturnWithExports {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("A module with pub items can't return a value of its own")
+    }
+}
+impl std::error::Error for ReturnWithExports {}
+
+/// Modules export their top level `pub` items only.
+#[derive(Debug)]
+struct NestedPub;
+impl std::fmt::Display for NestedPub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("pub items can only be declared at the top level of a module")
+    }
+}
+impl std::error::Error for NestedPub {}
+
+/// Class fields are set one by one on each instance (Or on the class, for
+/// static ones), so they can't be destructured.
+#[derive(Debug)]
+struct DestructuredField(String);
+impl std::fmt::Display for DestructuredField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The fields of class {} can't be destructured", self.0)
+    }
+}
+impl std::error::Error for DestructuredField {}
+
+/// Only the tasks of async functions can be suspended by `await`.
+#[derive(Debug)]
+struct AwaitOutsideAsync;
+impl std::fmt::Display for AwaitOutsideAsync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("await can only be used inside async functions")
+    }
+}
+impl std::error::Error for AwaitOutsideAsync {}
+
+/// Only generators and async functions run in a coroutine, which `yield`
+/// suspends.
+#[derive(Debug)]
+struct YieldOutsideCoroutine;
+impl std::fmt::Display for YieldOutsideCoroutine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("yield can only be used inside generator and async functions")
+    }
+}
+impl std::error::Error for YieldOutsideCoroutine {}
+
+/// `if` expressions are wrapped in a function, which would swallow them.
+#[derive(Debug)]
+struct ExitFromIfExpression(&'static str);
+impl std::fmt::Display for ExitFromIfExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} can't be used inside of an if expression, only in if statements and let bindings",
+            self.0
+        )
+    }
+}
+impl std::error::Error for ExitFromIfExpression {}
+
+/// Methods whose first argument is "self" belong to the instances.
+fn is_instance_method(f: &ast::Function) -> bool {
+    f.arguments
+        .first()
+        .map(|first| first.name.0 == "self")
+        .unwrap_or(false)
+}
+
+/// Emits an instance `__index` that dispatches getters first, then looks up
+/// the prototype, and falls back to the overloaded `[]` operator.
+fn instance_index(ctx: Builder, class_name: &str) -> Builder {
+    let index_fn = generate_operator_function_name("[]".into());
+    ctx.line()
+        .put(format!(
+            "{}.prototype.__meta__.__index = function(self, key)",
+            class_name
+        ))
+        .push()
+        .line()
+        .put(format!(
+            "local getter = {0}.__getters__ and {0}.__getters__[key];",
+            class_name
+        ))
+        .line()
+        .put("if getter ~= nil then")
+        .push()
+        .line()
+        .put("return getter(self);")
+        .pop()
+        .unwrap()
+        .line()
+        .put("end")
+        .line()
+        .put(format!("local value = {}.prototype[key];", class_name))
+        .line()
+        .put(format!(
+            "if value == nil and {0}.prototype.{1} ~= nil then",
+            class_name, index_fn
+        ))
+        .push()
+        .line()
+        .put(format!(
+            "return {}.prototype.{}(self, key);",
+            class_name, index_fn
+        ))
+        .pop()
+        .unwrap()
+        .line()
+        .put("end")
+        .line()
+        .put("return value;")
+        .pop()
+        .unwrap()
+        .line()
+        .put("end;")
+}
+
+/// Emits an instance `__newindex` that dispatches setters, or stores the
+/// value in the instance.
+fn instance_newindex(ctx: Builder, class_name: &str) -> Builder {
+    ctx.line()
+        .put(format!(
+            "{}.prototype.__meta__.__newindex = function(self, key, value)",
+            class_name
+        ))
+        .push()
+        .line()
+        .put(format!(
+            "local setter = {0}.__setters__ and {0}.__setters__[key];",
+            class_name
+        ))
+        .line()
+        .put("if setter ~= nil then")
+        .push()
+        .line()
+        .put("setter(self, value);")
+        .pop()
+        .unwrap()
+        .line()
+        .put("else")
+        .push()
+        .line()
+        .put("rawset(self, key, value);")
+        .pop()
+        .unwrap()
+        .line()
+        .put("end")
+        .pop()
+        .unwrap()
+        .line()
+        .put("end;")
+}
+
+/// A single link of a member or call chain.
+enum ChainLink<'a> {
+    Index(&'a ast::Expression),
+    Field(&'a ast::Identifier),
+    Call(&'a [ast::Expression]),
+    Method(&'a ast::Identifier, &'a [ast::Expression]),
+}
+
+/// A chain link, which is skipped along with the rest of the chain if the
+/// value it applies to is nil, when optional.
+struct ChainStep<'a> {
+    optional: bool,
+    link: ChainLink<'a>,
+}
+impl<'a> From<&'a ast::MemberSegment> for ChainStep<'a> {
+    fn from(segment: &'a ast::MemberSegment) -> Self {
+        let (optional, link) = match segment {
+            ast::MemberSegment::Computed(key) => (false, ChainLink::Index(key)),
+            ast::MemberSegment::Identifier(name) | ast::MemberSegment::Dispatch(name) => {
+                (false, ChainLink::Field(name))
+            }
+            ast::MemberSegment::OptionalComputed(key) => (true, ChainLink::Index(key)),
+            ast::MemberSegment::OptionalIdentifier(name) => (true, ChainLink::Field(name)),
+        };
+        ChainStep { optional, link }
+    }
+}
+
+/// The head and steps of an optional member or call chain, if the expression
+/// is one.
+fn optional_chain(expr: &ast::Expression) -> Option<(&ast::Expression, Vec<ChainStep<'_>>)> {
+    match expr {
+        ast::Expression::Reference(member) if member.is_optional() => Some((
+            &member.head,
+            member.tail.iter().map(ChainStep::from).collect(),
+        )),
+        ast::Expression::Call(call) if call.is_optional() => call
+            .head
+            .callee
+            .as_ref()
+            .map(|callee| (&callee.head, call_chain(call))),
+        _ => None,
+    }
+}
+
+/// Flattens a call expression into the steps of a chain.
+fn call_chain(expr: &ast::CallExpression) -> Vec<ChainStep<'_>> {
+    let mut steps = vec![];
+    let callee_tail = expr
+        .head
+        .callee
+        .as_ref()
+        .map(|callee| callee.tail.as_slice())
+        .unwrap_or_default();
+    match callee_tail.split_last() {
+        Some((ast::MemberSegment::Dispatch(name), init)) => {
+            steps.extend(init.iter().map(ChainStep::from));
+            steps.push(ChainStep {
+                optional: expr.head.optional,
+                link: ChainLink::Method(name, &expr.head.arguments),
+            });
+        }
+        _ => {
+            steps.extend(callee_tail.iter().map(ChainStep::from));
+            steps.push(ChainStep {
+                optional: expr.head.optional,
+                link: ChainLink::Call(&expr.head.arguments),
+            });
+        }
+    }
+    let mut tail = expr.tail.iter().peekable();
+    while let Some(elem) = tail.next() {
+        match elem {
+            ast::CallExpressionVariant::Member(ast::MemberSegment::Dispatch(name)) => {
+                if let Some(ast::CallExpressionVariant::Call(call)) = tail.peek() {
+                    steps.push(ChainStep {
+                        optional: call.optional,
+                        link: ChainLink::Method(name, &call.arguments),
+                    });
+                    tail.next();
+                } else {
+                    steps.push(ChainStep {
+                        optional: false,
+                        link: ChainLink::Field(name),
+                    });
+                }
+            }
+            ast::CallExpressionVariant::Member(segment) => steps.push(segment.into()),
+            ast::CallExpressionVariant::Call(call) => steps.push(ChainStep {
+                optional: call.optional,
+                link: ChainLink::Call(&call.arguments),
+            }),
+        }
+    }
+    steps
+}
+
+/// Whether the block may return from its enclosing function, at any depth
+/// short of the functions it declares.
+fn returns(script: &Script) -> bool {
+    script.statements.iter().any(statement_returns)
+}
+
+fn statement_returns(stmt: &ast::Statement) -> bool {
+    match stmt {
+        ast::Statement::Return(_) => true,
+        ast::Statement::MacroDecorator(e) => statement_returns(&e.target),
+        ast::Statement::If(e) => {
+            returns(&e.body)
+                || e.branches.iter().any(|(_, body)| returns(body))
+                || e.else_branch.as_ref().is_some_and(returns)
+        }
+        ast::Statement::Try(e) => {
+            returns(&e.body)
+                || e.catch.as_ref().is_some_and(|catch| returns(&catch.body))
+                || e.finally.as_ref().is_some_and(returns)
+        }
+        ast::Statement::For(e) => returns(&e.body),
+        ast::Statement::While(e) => returns(&e.body),
+        ast::Statement::Loop(e) => returns(&e.body),
+        ast::Statement::Let(e) => e.else_branch.as_ref().is_some_and(returns),
+        ast::Statement::Expression(ast::Expression::Do(e)) => returns(&e.body),
+        _ => false,
+    }
+}
+
+/// Whether the statement declares a `pub` item.
+fn is_public(stmt: &ast::Statement) -> bool {
+    match stmt {
+        ast::Statement::MacroDecorator(e) => is_public(&e.target),
+        ast::Statement::Function(e) => e.public,
+        ast::Statement::Class(e) => e.public,
+        ast::Statement::Enum(e) => e.public,
+        ast::Statement::Let(e) => e.public,
+        _ => false,
+    }
+}
+
+/// Where an `if` expression or an optional chain lowered to statements
+/// leaves its value.
+#[derive(Clone, Copy)]
+enum ValueSink<'a> {
+    /// Assigns a temporary declared right before, which starts as `nil`.
+    Declared(&'a str),
+    /// Declares a new local once the value is known.
+    Bind(&'a str),
+    Assign(&'a str),
+    Return,
+    /// Evaluates the value for its side effects only.
+    Discard,
+}
+
+/// How `return` and `break` statements leave the code being emitted. Inside
+/// the protected functions of `try` and `defer` blocks, they return a tag
+/// instead, which the enclosing block acts upon once it is left.
+#[derive(Clone, Copy, Default)]
+struct Exits {
+    /// How the enclosing function is left.
+    returns: Exit,
+    /// How the enclosing loop is left.
+    breaks: Exit,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Exit {
+    #[default]
+    Direct,
+    /// Through a protected function.
+    Protected,
+    /// Not at all, from the function an `if` expression is wrapped in.
+    Enclosed,
+}
+
+/// Whether the expression statement is a `break`, which is parsed as a name.
+fn is_break(expr: &ast::Expression) -> bool {
+    let head = match expr {
+        ast::Expression::Reference(member) if member.tail.is_empty() => &member.head,
+        expr => expr,
+    };
+    matches!(head, ast::Expression::Identifier(id) if id.0 == "break")
+}
+
+/// The Lua name of an argument, destructured ones are bound to a temporary.
+fn argument_name(i: usize, arg: &ast::Argument) -> String {
+    if arg.pattern.is_some() {
+        format!("__argument_{i}__")
+    } else {
+        arg.name.0.clone()
+    }
 }
 
 pub struct LuaEmitter {
-    // pub module_mapping: HashMap<String, PathBuf>,
     pub macro_host: MacroHost,
+    /// Checks that modules named by `use` statements exist, if set.
+    pub resolver: Option<FileSystemResolver>,
+    /// The modules named by the `use` statements visited so far.
+    pub used_modules: RefCell<Vec<String>>,
+    /// Whether modules that no root provides are left for Lua to find at
+    /// runtime, instead of failing the compilation.
+    pub allow_unresolved: bool,
+    /// The modules left for Lua to find, so the caller can warn about them.
+    pub unresolved_modules: RefCell<Vec<UnresolvedModule>>,
+    /// What the `use` statements visited so far import from std.
+    pub std_usage: RefCell<StdUsage>,
+    /// How many `let` bound `if` expressions enclose the code being emitted,
+    /// so each one stores its value in a temporary of its own.
+    if_values: Cell<usize>,
+    exits: Cell<Exits>,
+    /// Whether a tagged `break` left the protected blocks being emitted.
+    protected_breaks: Cell<bool>,
+    /// Whether the script has `try` or `defer` blocks, which need the helper.
+    uses_try: Cell<bool>,
+    /// The kind of function whose body is being emitted, `Plain` outside of
+    /// any function.
+    function_kind: Cell<ast::FunctionKind>,
+    /// Whether the script awaits, which needs the task stepping helper.
+    uses_await: Cell<bool>,
+    /// Whether the block being visited is the script itself.
+    top_level: Cell<bool>,
 }
 
 impl LuaEmitter {
     pub fn new(info: InputFileInfo) -> Self {
         Self {
-            macro_host: MacroHost::new(info), // module_mapping: HashMap::new(),
-        }
-    }
-    // pub fn map_module_path(&self, segments: &Vec<String>) -> String {
-    //     let path = segments.join(".");
-    //     let re = regex::Regex::new(r"\.saturn$").unwrap();
-    //     if let Some(found) = self.module_mapping.get(&path) {
-    //         let found = found
-    //             .iter()
-    //             .map(|p| p.to_str().unwrap().to_owned())
-    //             .collect::<Vec<String>>()
-    //             .join("/");
-    //         let found = re.replace_all(&found, "").to_string();
-    //         format!("./{found}")
-    //     } else {
-    //         path
-    //     }
-    // }
-    pub fn escape_reference(&self, ctx: Builder, ident: &ast::Identifier) -> Result {
-        let ctx = match &ident.0 {
-            a if a == "then" => ctx.put("['then']"),
-            ident => ctx.put(".").put(ident.clone()),
+            macro_host: MacroHost::new(info),
+            resolver: None,
+            used_modules: RefCell::new(Vec::new()),
+            allow_unresolved: false,
+            unresolved_modules: RefCell::new(Vec::new()),
+            std_usage: RefCell::new(StdUsage::default()),
+            if_values: Cell::new(0),
+            exits: Cell::new(Exits::default()),
+            protected_breaks: Cell::new(false),
+            uses_try: Cell::new(false),
+            function_kind: Cell::new(ast::FunctionKind::Plain),
+            uses_await: Cell::new(false),
+            top_level: Cell::new(false),
+        }
+    }
+    pub fn with_resolver(self, resolver: FileSystemResolver) -> Self {
+        Self {
+            resolver: Some(resolver),
+            ..self
+        }
+    }
+    pub fn with_unresolved_allowed(self, allow_unresolved: bool) -> Self {
+        Self {
+            allow_unresolved,
+            ..self
+        }
+    }
+    /// Fails unless a root holds the module, the std library and the Lua
+    /// libraries are built in. Checked modules are recorded, so they can be
+    /// bundled afterwards.
+    fn check_module(&self, module: &str) -> std::result::Result<(), UnresolvedModule> {
+        let Some(resolver) = self.resolver.as_ref() else {
+            return Ok(());
         };
-        Ok(ctx)
+        if module == "std" || LUA_LIBRARIES.contains(&module) {
+            return Ok(());
+        }
+        match resolver.locate(module) {
+            Ok(_) => self.used_modules.borrow_mut().push(module.to_owned()),
+            Err(err) if self.allow_unresolved => self.unresolved_modules.borrow_mut().push(err),
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+    /// Records the std exports picked by a `use` statement, nested tables
+    /// (Like the operators) by the names picked from them.
+    fn record_std_usage(&self, expanded: Option<&ast::Destructuring>) {
+        let mut usage = self.std_usage.borrow_mut();
+        let Some(expr) = expanded.filter(|e| matches!(e.origin, ast::DestructureOrigin::Table))
+        else {
+            *usage = StdUsage::All;
+            return;
+        };
+        for seg in expr.targets.iter() {
+            match seg {
+                ast::DestructuringSegment::Identifier(id)
+                | ast::DestructuringSegment::Default((id, _)) => usage.import([id.0.clone()]),
+                ast::DestructuringSegment::Destructuring((id, nested)) => {
+                    let mut names = Vec::new();
+                    self.collect_targets(nested, &mut names);
+                    let flat = nested.targets.iter().all(|seg| {
+                        matches!(
+                            seg,
+                            ast::DestructuringSegment::Identifier(_)
+                                | ast::DestructuringSegment::Default(_)
+                        )
+                    });
+                    if flat {
+                        usage.import(names.into_iter().map(|id| id.0));
+                    } else {
+                        usage.import([id.0.clone()]);
+                    }
+                }
+                ast::DestructuringSegment::Rest(_) => *usage = StdUsage::All,
+            }
+        }
+    }
+    pub fn escape_reference(&self, ctx: Builder, ident: &ast::Identifier) -> Result {
+        Ok(ctx.put(field_access(&ident.0)))
     }
     pub fn generate_member_segment<S>(
         &self,
@@ -71,30 +570,351 @@ impl LuaEmitter {
         S: Visitor,
     {
         match elem {
-            ast::MemberSegment::Computed(c) => {
+            ast::MemberSegment::Computed(c) | ast::MemberSegment::OptionalComputed(c) => {
                 let ctx = ctx.put("[");
-                let ctx = s.visit_expression(ctx, &c)?;
+                let ctx = s.visit_expression(ctx, c)?;
+                Ok(ctx.put("]"))
+            }
+            ast::MemberSegment::Identifier(i)
+            | ast::MemberSegment::Dispatch(i)
+            | ast::MemberSegment::OptionalIdentifier(i) => self.escape_reference(ctx, i),
+        }
+    }
+    /// Emits a class method as a function, with "Self" bound to the class and
+    /// "super" to the parent's prototype, or the parent class if static.
+    fn visit_method_body(
+        &self,
+        ctx: Builder,
+        class: &ast::Class,
+        f: &ast::Function,
+        is_static: bool,
+    ) -> Result {
+        let mut body = f.body.clone();
+        body.statements.insert(
+            0,
+            ast::Statement::Let(ast::Let {
+                target: ast::AssignmentTarget::Identifier(Identifier("Self".into())),
+                value: Some(ast::Expression::Identifier(class.name.clone())),
+                else_branch: None,
+                public: false,
+            }),
+        );
+        if let Some(parent) = &class.parent {
+            let value = if is_static {
+                ast::Expression::Identifier(parent.clone())
+            } else {
+                ast::Expression::Reference(Box::new(ast::MemberExpression {
+                    head: ast::Expression::Identifier(parent.clone()),
+                    tail: vec![ast::MemberSegment::Identifier(Identifier(
+                        "prototype".into(),
+                    ))],
+                }))
+            };
+            body.statements.insert(
+                1,
+                ast::Statement::Let(ast::Let {
+                    target: ast::AssignmentTarget::Identifier(Identifier("super".into())),
+                    value: Some(value),
+                    else_branch: None,
+                    public: false,
+                }),
+            );
+        }
+        self.visit_lambda(
+            ctx,
+            &ast::Lambda {
+                arguments: f.arguments.clone(),
+                body: ast::ScriptOrExpression::Script(body),
+                kind: f.kind,
+            },
+        )
+    }
+
+    /// Emits a class method, followed by its decorators.
+    fn visit_class_method(
+        &self,
+        ctx: Builder,
+        class: &ast::Class,
+        f: &ast::Function,
+        is_static: bool,
+    ) -> Result {
+        let fn_ref = if is_static {
+            format!("{}.{}", class.name.0, f.name.0)
+        } else {
+            format!("{}.prototype.{}", class.name.0, f.name.0)
+        };
+        let ctx = ctx.put(format!("{} = ", fn_ref));
+        let ctx = self.visit_method_body(ctx, class, f, is_static)?.put(";");
+        f.decorators.iter().try_fold(ctx, |ctx, dec| {
+            let ctx = ctx.line();
+            let ctx = self.visit_call(ctx, &dec.target)?;
+            Ok(ctx.put(format!(
+                "({}, \"{}\", {}, \"{}\", {{ is_static = {} }});",
+                fn_ref, f.name.0, class.name.0, class.name.0, is_static
+            )))
+        })
+    }
+
+    /// Emits the prologue that assigns the default values of the arguments
+    /// left as `nil` by the caller, and unpacks the destructured ones.
+    fn visit_argument_prologue(&self, ctx: Builder, args: &[ast::Argument]) -> Result {
+        args.iter().enumerate().try_fold(ctx, |ctx, (i, arg)| {
+            let name = Identifier(argument_name(i, arg));
+            let ctx = match arg.default.as_ref() {
+                Some(default) => self.gen_default(ctx, &name, default)?,
+                None => ctx,
+            };
+            match arg.pattern.as_ref() {
+                Some(pattern) => {
+                    self.gen_destruct(ctx, &ast::Expression::Identifier(name), pattern)
+                }
+                None => Ok(ctx),
+            }
+        })
+    }
+
+    fn visit_arguments(&self, ctx: Builder, args: &[ast::Expression]) -> Result {
+        let ctx = ctx.put("(");
+        let ctx = args.iter().enumerate().try_fold(ctx, |ctx, (i, arg)| {
+            let ctx = if i > 0 { ctx.put(", ") } else { ctx };
+            self.visit_expression(ctx, arg)
+        })?;
+        Ok(ctx.put(")"))
+    }
+
+    /// Emits the link of a chain applied to the `__chain__` temporary.
+    fn gen_chain_link(&self, ctx: Builder, link: &ChainLink) -> Result {
+        match link {
+            ChainLink::Index(key) => {
+                let ctx = self.visit_expression(ctx.put("["), key)?;
                 Ok(ctx.put("]"))
             }
-            ast::MemberSegment::Identifier(i) => self.escape_reference(ctx, i),
-            ast::MemberSegment::Dispatch(i) => self.escape_reference(ctx, i),
+            ChainLink::Field(name) => self.escape_reference(ctx, name),
+            ChainLink::Call(args) => self.visit_arguments(ctx, args),
+            // Keywords can't follow ":", so the receiver is passed by hand.
+            ChainLink::Method(name, args) if LUA_KEYWORDS.contains(&name.0.as_str()) => {
+                let receiver = ast::Expression::Identifier(Identifier("__chain__".into()));
+                let args = [vec![receiver], args.to_vec()].concat();
+                self.visit_arguments(ctx.put(field_access(&name.0)), &args)
+            }
+            ChainLink::Method(name, args) => {
+                self.visit_arguments(ctx.put(":").put(name.0.clone()), args)
+            }
+        }
+    }
+
+    /// Emits a member or call chain with nil-safe links as statements that
+    /// walk it through a local, so nothing is evaluated twice. Each nil-safe
+    /// link opens a guard, so the local is left as `nil` once a link is
+    /// skipped.
+    fn gen_chain(
+        &self,
+        ctx: Builder,
+        head: &ast::Expression,
+        steps: &[ChainStep],
+        sink: ValueSink,
+    ) -> Result {
+        // Only a new binding needs the local to outlive the chain.
+        let scoped = !matches!(sink, ValueSink::Bind(_));
+        let ctx = if scoped {
+            ctx.line().put("do").push()
+        } else {
+            ctx
+        };
+        let ctx = ctx.line().put("local __chain__ = ");
+        let ctx = self.visit_expression(ctx, head)?.put(";");
+        let mut guards = vec![];
+        let ctx = steps.iter().enumerate().try_fold(ctx, |ctx, (i, step)| {
+            let ctx = if step.optional {
+                let method = match &step.link {
+                    ChainLink::Method(name, _) => Some(name),
+                    _ => None,
+                };
+                let check = match method {
+                    Some(name) => format!(
+                        "__chain__ ~= nil and __chain__{} ~= nil",
+                        field_access(&name.0)
+                    ),
+                    None => "__chain__ ~= nil".into(),
+                };
+                guards.push(method.is_some());
+                ctx.line().put(format!("if {check} then")).push()
+            } else {
+                ctx
+            };
+            // The last link goes straight to the sink, keeping all the call
+            // results when returned.
+            let ctx = match (i + 1 == steps.len(), sink, &step.link) {
+                (true, ValueSink::Return, _) => self.gen_return(ctx).put("__chain__"),
+                (true, ValueSink::Discard, ChainLink::Call(_) | ChainLink::Method(..)) => {
+                    ctx.line().put("__chain__")
+                }
+                (true, ValueSink::Discard, _) => ctx.line().put("local _ = __chain__"),
+                _ => ctx.line().put("__chain__ = __chain__"),
+            };
+            Ok(self.gen_chain_link(ctx, &step.link)?.put(";"))
+        })?;
+        let keeps_value = matches!(
+            sink,
+            ValueSink::Declared(_) | ValueSink::Bind(_) | ValueSink::Assign(_)
+        );
+        let ctx = guards.iter().rev().fold(ctx, |ctx, method| {
+            // A missing method leaves the receiver in the local.
+            let ctx = if *method && keeps_value {
+                ctx.pop()
+                    .unwrap()
+                    .line()
+                    .put("else")
+                    .push()
+                    .line()
+                    .put("__chain__ = nil;")
+            } else {
+                ctx
+            };
+            ctx.pop().unwrap().line().put("end")
+        });
+        let ctx = match sink {
+            ValueSink::Declared(target) | ValueSink::Assign(target) => {
+                ctx.line().put(format!("{target} = __chain__;"))
+            }
+            ValueSink::Bind(target) => ctx.line().put(format!("local {target} = __chain__;")),
+            ValueSink::Return => self.gen_return(ctx).put("nil;"),
+            ValueSink::Discard => ctx,
+        };
+        if scoped {
+            Ok(ctx.pop().unwrap().line().put("end"))
+        } else {
+            Ok(ctx)
         }
     }
+
+    /// Emits a member or call chain with nil-safe links nested in another
+    /// expression as a function that walks it through a temporary, so nothing
+    /// is evaluated twice. Chains that are the whole value of a statement are
+    /// emitted by `gen_chain` instead.
+    fn visit_chain(&self, ctx: Builder, head: &ast::Expression, steps: &[ChainStep]) -> Result {
+        let ctx = ctx
+            .put("(function()")
+            .push()
+            .line()
+            .put("local __chain__ = ");
+        let ctx = self.visit_expression(ctx, head)?.put(";");
+        let ctx = steps.iter().enumerate().try_fold(ctx, |ctx, (i, step)| {
+            let ctx = if step.optional {
+                let check = match &step.link {
+                    ChainLink::Method(name, _) => format!(
+                        "if __chain__ == nil or __chain__{} == nil then return nil; end",
+                        field_access(&name.0)
+                    ),
+                    _ => "if __chain__ == nil then return nil; end".into(),
+                };
+                ctx.line().put(check)
+            } else {
+                ctx
+            };
+            // The last link is returned as-is, keeping all the call results.
+            let ctx = if i + 1 == steps.len() {
+                ctx.line().put("return __chain__")
+            } else {
+                ctx.line().put("__chain__ = __chain__")
+            };
+            Ok(self.gen_chain_link(ctx, &step.link)?.put(";"))
+        })?;
+        let ctx = if steps.is_empty() {
+            ctx.line().put("return __chain__;")
+        } else {
+            ctx
+        };
+        Ok(ctx.pop().unwrap().line().put("end)()"))
+    }
+
     fn collect_targets(&self, expr: &ast::Destructuring, targets: &mut Vec<Identifier>) {
         for seg in expr.targets.iter() {
             match seg {
-                ast::DestructuringSegment::Identifier(id) => targets.push(id.clone()),
+                ast::DestructuringSegment::Identifier(id)
+                | ast::DestructuringSegment::Default((id, _))
+                | ast::DestructuringSegment::Rest(id) => targets.push(id.clone()),
                 ast::DestructuringSegment::Destructuring((_, expr)) => {
                     self.collect_targets(expr, targets)
                 }
             }
         }
     }
+    /// The top-level names declared with `pub`, in declaration order.
+    fn module_exports(&self, script: &Script) -> Vec<Identifier> {
+        let mut exports = Vec::new();
+        for stmt in script.statements.iter() {
+            let mut stmt = stmt;
+            while let ast::Statement::MacroDecorator(e) = stmt {
+                stmt = &e.target;
+            }
+            match stmt {
+                ast::Statement::Function(e) if e.public => exports.push(e.name.clone()),
+                ast::Statement::Class(e) if e.public => exports.push(e.name.clone()),
+                ast::Statement::Enum(e) if e.public => exports.push(e.name.clone()),
+                ast::Statement::Let(e) if e.public => match &e.target {
+                    ast::AssignmentTarget::Identifier(id) => exports.push(id.clone()),
+                    ast::AssignmentTarget::Destructuring(expr) => {
+                        self.collect_targets(expr, &mut exports)
+                    }
+                },
+                _ => {}
+            }
+        }
+        exports
+    }
     pub fn gen_destruct(
         &self,
         ctx: Builder,
         source: &ast::Expression,
         expr: &ast::Destructuring,
+    ) -> Result {
+        self.gen_destruct_else(ctx, source, expr, None)
+    }
+    /// Like `gen_destruct`, but runs the else block instead when the source
+    /// is `nil`.
+    fn gen_destruct_else(
+        &self,
+        ctx: Builder,
+        source: &ast::Expression,
+        expr: &ast::Destructuring,
+        else_branch: Option<&Script>,
+    ) -> Result {
+        self.gen_destruct_from(ctx, source, expr, else_branch, None)
+    }
+    /// Raises if the required module lacks any of the top-level names that
+    /// are picked without a default value.
+    fn gen_export_checks(&self, ctx: Builder, module: &str, expr: &ast::Destructuring) -> Builder {
+        if !matches!(expr.origin, ast::DestructureOrigin::Table) {
+            return ctx;
+        }
+        expr.targets.iter().fold(ctx, |ctx, seg| match seg {
+            ast::DestructuringSegment::Identifier(id)
+            | ast::DestructuringSegment::Destructuring((id, _)) => ctx
+                .line()
+                .put(format!("if __destructure__.{} == nil then", id.0))
+                .push()
+                .line()
+                .put(format!(
+                    "error({});",
+                    quote_string(format!("Module {module} does not export {}", id.0).as_bytes())
+                ))
+                .pop()
+                .unwrap()
+                .line()
+                .put("end"),
+            ast::DestructuringSegment::Default(_) | ast::DestructuringSegment::Rest(_) => ctx,
+        })
+    }
+    /// Shared by the destructuring forms, `module` names the source when it
+    /// is a `use` statement, so its exports are checked.
+    fn gen_destruct_from(
+        &self,
+        ctx: Builder,
+        source: &ast::Expression,
+        expr: &ast::Destructuring,
+        else_branch: Option<&Script>,
+        module: Option<&str>,
     ) -> Result {
         let mut targets = Vec::<Identifier>::new();
         self.collect_targets(expr, &mut targets);
@@ -107,51 +927,369 @@ impl LuaEmitter {
         let ctx = ctx.line().put("do").push();
         let ctx = ctx.line().put("local __destructure__ = ");
         let ctx = self.visit_expression(ctx, source)?.put(";");
-        let ctx = self.gen("__destructure__".to_owned(), ctx, expr);
+        let ctx = match else_branch {
+            Some(else_branch) => self.gen_nil_guard(ctx, "__destructure__", else_branch)?,
+            None => ctx,
+        };
+        let ctx = match module {
+            Some(module) => self.gen_export_checks(ctx, module, expr),
+            None => ctx,
+        };
+        let ctx = self.gen("__destructure__".to_owned(), ctx, expr)?;
         let ctx = ctx.pop().unwrap().line().put("end");
         Ok(ctx)
     }
-    fn gen(&self, path: String, ctx: Builder, expr: &ast::Destructuring) -> Builder {
-        match expr.origin {
-            ast::DestructureOrigin::Tuple => {
-                expr.targets
-                    .iter()
-                    .fold((ctx, 0u8), |(ctx, i), elem| match elem {
-                        ast::DestructuringSegment::Identifier(id) => (
-                            ctx.line().put(format!("{} = {}._{};", id.0, path, i)),
-                            i + 1,
-                        ),
-                        ast::DestructuringSegment::Destructuring((_, dt)) => {
-                            (self.gen(format!("{path}._{i}"), ctx, dt), i + 1)
-                        }
-                    })
-                    .0
-            }
-            ast::DestructureOrigin::Array => {
-                expr.targets
-                    .iter()
-                    .fold((ctx, 1u8), |(ctx, i), elem| match elem {
-                        ast::DestructuringSegment::Identifier(id) => (
-                            ctx.line().put(format!("{} = {}[{}];", id.0, path, i)),
-                            i + 1,
-                        ),
-                        ast::DestructuringSegment::Destructuring((_, dt)) => {
-                            (self.gen(format!("{path}[{i}]"), ctx, dt), i + 1)
-                        }
-                    })
-                    .0
-            }
-            ast::DestructureOrigin::Table => {
-                expr.targets.iter().fold(ctx, |ctx, elem| match elem {
-                    ast::DestructuringSegment::Identifier(id) => {
-                        ctx.line().put(format!("{} = {}.{};", id.0, path, id.0))
-                    }
-                    ast::DestructuringSegment::Destructuring((id, dt)) => {
-                        self.gen(format!("{}.{}", path, id.0), ctx, dt)
-                    }
-                })
-            }
+    fn gen(&self, path: String, ctx: Builder, expr: &ast::Destructuring) -> Result {
+        let element = |i: usize| match expr.origin {
+            ast::DestructureOrigin::Tuple => format!("{path}._{i}"),
+            ast::DestructureOrigin::Array => format!("{path}[{}]", i + 1),
+            ast::DestructureOrigin::Table => unreachable!(),
+        };
+        match expr.origin {
+            ast::DestructureOrigin::Tuple | ast::DestructureOrigin::Array => expr
+                .targets
+                .iter()
+                .enumerate()
+                .try_fold(ctx, |ctx, (i, elem)| match elem {
+                    ast::DestructuringSegment::Identifier(id) => {
+                        Ok(ctx.line().put(format!("{} = {};", id.0, element(i))))
+                    }
+                    ast::DestructuringSegment::Default((id, default)) => {
+                        let ctx = ctx.line().put(format!("{} = {};", id.0, element(i)));
+                        self.gen_default(ctx, id, default)
+                    }
+                    ast::DestructuringSegment::Rest(id) => Ok(ctx.line().put(format!(
+                        "{} = {{ __saturnus_spread__({}, {}) }};",
+                        id.0,
+                        path,
+                        i + 1
+                    ))),
+                    ast::DestructuringSegment::Destructuring((_, dt)) => {
+                        self.gen(element(i), ctx, dt)
+                    }
+                }),
+            ast::DestructureOrigin::Table => {
+                expr.targets.iter().try_fold(ctx, |ctx, elem| match elem {
+                    ast::DestructuringSegment::Identifier(id) => {
+                        Ok(ctx.line().put(format!("{} = {}.{};", id.0, path, id.0)))
+                    }
+                    ast::DestructuringSegment::Default((id, default)) => {
+                        let ctx = ctx.line().put(format!("{} = {}.{};", id.0, path, id.0));
+                        self.gen_default(ctx, id, default)
+                    }
+                    ast::DestructuringSegment::Rest(id) => {
+                        let taken = expr
+                            .targets
+                            .iter()
+                            .filter_map(|elem| match elem {
+                                ast::DestructuringSegment::Identifier(key)
+                                | ast::DestructuringSegment::Default((key, _))
+                                | ast::DestructuringSegment::Destructuring((key, _)) => {
+                                    Some(format!("__key__ ~= \"{}\"", key.0))
+                                }
+                                ast::DestructuringSegment::Rest(_) => None,
+                            })
+                            .collect::<Vec<_>>();
+                        let ctx = ctx
+                            .line()
+                            .put(format!("{} = {{}};", id.0))
+                            .line()
+                            .put(format!("for __key__, __value__ in pairs({path}) do"))
+                            .push();
+                        let ctx = if taken.is_empty() {
+                            ctx.line().put(format!("{}[__key__] = __value__;", id.0))
+                        } else {
+                            ctx.line()
+                                .put(format!("if {} then", taken.join(" and ")))
+                                .push()
+                                .line()
+                                .put(format!("{}[__key__] = __value__;", id.0))
+                                .pop()
+                                .unwrap()
+                                .line()
+                                .put("end")
+                        };
+                        Ok(ctx.pop().unwrap().line().put("end"))
+                    }
+                    ast::DestructuringSegment::Destructuring((id, dt)) => {
+                        self.gen(format!("{}.{}", path, id.0), ctx, dt)
+                    }
+                })
+            }
+        }
+    }
+    /// Emits an `if` expression as an `if` statement, each branch handing its
+    /// value to the sink. Nested `if` values reuse the same sink.
+    fn gen_if(&self, ctx: Builder, expr: &ast::IfExpression, sink: ValueSink) -> Result {
+        let ctx = ctx.line().put("if ");
+        let ctx = self.visit_expression(ctx, &expr.condition)?;
+        let ctx = ctx.put(" then").push();
+        let ctx = self.gen_if_branch(ctx, &expr.body, sink)?;
+        let ctx = expr.branches.iter().try_fold(ctx, |ctx, (c, b)| {
+            let ctx = ctx.pop().unwrap().line().put("elseif ");
+            let ctx = self.visit_expression(ctx, c)?;
+            let ctx = ctx.put(" then").push();
+            self.gen_if_branch(ctx, b, sink)
+        })?;
+        let ctx = match (&expr.else_branch, sink) {
+            (Some(b), _) => {
+                let ctx = ctx.pop().unwrap().line().put("else").push();
+                self.gen_if_branch(ctx, b, sink)?
+            }
+            (None, ValueSink::Assign(_) | ValueSink::Return) => {
+                let ctx = ctx.pop().unwrap().line().put("else").push();
+                self.gen_if_value(ctx, &ast::Expression::Unit, sink)?
+            }
+            (None, _) => ctx,
+        };
+        Ok(ctx.pop().unwrap().line().put("end"))
+    }
+    fn gen_if_branch(&self, ctx: Builder, block: &ast::ValueBlock, sink: ValueSink) -> Result {
+        let ctx = self.visit_block(ctx, &block.body)?;
+        match (&block.value, sink) {
+            (Some(value), _) => self.gen_if_value(ctx, value, sink),
+            // A branch that already returned can't be followed by a statement.
+            (None, ValueSink::Return)
+                if matches!(
+                    block.body.statements.last(),
+                    Some(ast::Statement::Return(_))
+                ) =>
+            {
+                Ok(ctx)
+            }
+            (None, ValueSink::Assign(_) | ValueSink::Return) => {
+                self.gen_if_value(ctx, &ast::Expression::Unit, sink)
+            }
+            (None, _) => Ok(ctx),
+        }
+    }
+    fn gen_if_value(&self, ctx: Builder, value: &ast::Expression, sink: ValueSink) -> Result {
+        if let ast::Expression::If(expr) = value {
+            return self.gen_if(ctx, expr, sink);
+        }
+        let ctx = match sink {
+            ValueSink::Declared(target) | ValueSink::Assign(target) => {
+                ctx.line().put(format!("{target} = "))
+            }
+            ValueSink::Bind(target) => ctx.line().put(format!("local {target} = ")),
+            ValueSink::Return => self.gen_return(ctx),
+            ValueSink::Discard if matches!(value, ast::Expression::Call(_)) => ctx.line(),
+            ValueSink::Discard => ctx.line().put("local _ = "),
+        };
+        Ok(self.visit_expression(ctx, value)?.put(";"))
+    }
+
+    /// Emits a protected block, with an optional handler, and the finally
+    /// code that runs before errors, returns and breaks are propagated.
+    fn gen_try<B, F>(&self, ctx: Builder, body: B, catch: Option<&ast::Catch>, finally: F) -> Result
+    where
+        B: FnOnce(Builder) -> Result,
+        F: FnOnce(Builder) -> Result,
+    {
+        self.uses_try.set(true);
+        let outer_breaks = self.protected_breaks.replace(false);
+        let ctx = ctx
+            .line()
+            .put("do")
+            .push()
+            .line()
+            .put("local __try_ok__, __try_result__ = ");
+        let ctx = self.gen_protected(ctx, body)?.put(";");
+        let ctx = if let Some(catch) = catch {
+            let ctx = ctx.line().put("if not __try_ok__ then").push();
+            let ctx = if let Some(name) = &catch.error {
+                ctx.line()
+                    .put(format!("local {} = __try_result__[1].error;", name.0))
+            } else {
+                ctx
+            };
+            let ctx = if let Some(name) = &catch.traceback {
+                ctx.line()
+                    .put(format!("local {} = __try_result__[1].traceback;", name.0))
+            } else {
+                ctx
+            };
+            let ctx = ctx.line().put("__try_ok__, __try_result__ = ");
+            let ctx = self
+                .gen_protected(ctx, |ctx| self.visit_block(ctx, &catch.body))?
+                .put(";");
+            ctx.pop().unwrap().line().put("end")
+        } else {
+            ctx
+        };
+        let breaks = self.protected_breaks.replace(outer_breaks);
+        let ctx = finally(ctx)?;
+        let ctx = ctx
+            .line()
+            .put("if not __try_ok__ then")
+            .push()
+            .line()
+            .put("error(__try_result__[1].error, 0);")
+            .pop()
+            .unwrap()
+            .line()
+            .put("end")
+            .line()
+            .put("if __try_result__[1] == \"return\" then")
+            .push();
+        let ctx = self
+            .gen_return(ctx)
+            .put("__saturnus_spread__(__try_result__, 2, __try_result__.n);")
+            .pop()
+            .unwrap()
+            .line()
+            .put("end");
+        let ctx = if breaks {
+            let ctx = ctx
+                .line()
+                .put("if __try_result__[1] == \"break\" then")
+                .push();
+            self.gen_break(ctx).pop().unwrap().line().put("end")
+        } else {
+            ctx
+        };
+        Ok(ctx.pop().unwrap().line().put("end"))
+    }
+    /// Emits an `xpcall` of the block, packing what it returns. The returns and
+    /// breaks in it return a tag first, falling off the end returns nothing.
+    fn gen_protected<B>(&self, ctx: Builder, body: B) -> Result
+    where
+        B: FnOnce(Builder) -> Result,
+    {
+        // Exits that aren't allowed stay so.
+        let protect = |exit| match exit {
+            Exit::Enclosed => Exit::Enclosed,
+            _ => Exit::Protected,
+        };
+        let outer = self.exits.get();
+        let exits = Exits {
+            returns: protect(outer.returns),
+            breaks: protect(outer.breaks),
+        };
+        let ctx = ctx.put("__saturnus_try__(xpcall(function()").push();
+        let ctx = self.with_exits(exits, || body(ctx))?;
+        Ok(ctx
+            .pop()
+            .unwrap()
+            .line()
+            .put("end, __saturnus_traceback__))"))
+    }
+    /// Emits code whose returns and breaks leave through the given exits.
+    fn with_exits<T>(&self, exits: Exits, emit: impl FnOnce() -> T) -> T {
+        let outer = self.exits.replace(exits);
+        let result = emit();
+        self.exits.set(outer);
+        result
+    }
+    /// Starts a return statement, tagged if it leaves a protected function.
+    fn gen_return(&self, ctx: Builder) -> Builder {
+        if self.exits.get().returns == Exit::Protected {
+            ctx.line().put("return \"return\", ")
+        } else {
+            ctx.line().put("return ")
+        }
+    }
+    /// Emits a break statement, tagged if it leaves a protected function.
+    fn gen_break(&self, ctx: Builder) -> Builder {
+        if self.exits.get().breaks == Exit::Protected {
+            self.protected_breaks.set(true);
+            ctx.line().put("return \"break\";")
+        } else {
+            ctx.line().put("break;")
+        }
+    }
+    /// Emits the statements of a block, running its deferred expressions
+    /// when left. Only the script itself may declare `pub` items.
+    fn gen_block(&self, ctx: Builder, script: &Script, top_level: bool) -> Result {
+        if !top_level && script.statements.iter().any(is_public) {
+            return Err(VisitError(Box::new(NestedPub)));
+        }
+        let visit_statements = |ctx: Builder| {
+            script
+                .statements
+                .iter()
+                .try_fold(ctx, |ctx, stmt| self.visit_statement(ctx, stmt))
+        };
+        let defers = script
+            .statements
+            .iter()
+            .any(|stmt| matches!(stmt, ast::Statement::Defer(_)));
+        if !defers {
+            return visit_statements(ctx);
         }
+        let ctx = ctx.line().put("local __defers__ = {};");
+        self.gen_try(ctx, visit_statements, None, |ctx| {
+            Ok(ctx
+                .line()
+                .put("for i = #__defers__, 1, -1 do")
+                .push()
+                .line()
+                .put("__defers__[i]();")
+                .pop()
+                .unwrap()
+                .line()
+                .put("end"))
+        })
+    }
+    /// Emits the body of a loop, which its breaks leave directly.
+    fn gen_loop_body(&self, ctx: Builder, body: &Script) -> Result {
+        let exits = Exits {
+            breaks: Exit::Direct,
+            ..self.exits.get()
+        };
+        self.with_exits(exits, || self.visit_block(ctx, body))
+    }
+    /// Emits the body of a function, wrapped in a coroutine for generators
+    /// and async functions.
+    fn gen_function_body(&self, ctx: Builder, kind: ast::FunctionKind, body: &Script) -> Result {
+        let outer_kind = self.function_kind.replace(kind);
+        let result = self.gen_function_kind_body(ctx, kind, body);
+        self.function_kind.set(outer_kind);
+        result
+    }
+    fn gen_function_kind_body(
+        &self,
+        ctx: Builder,
+        kind: ast::FunctionKind,
+        body: &Script,
+    ) -> Result {
+        let ctx = match kind {
+            ast::FunctionKind::Plain => {
+                return self.with_exits(Exits::default(), || self.visit_block(ctx, body))
+            }
+            // An iterator that resumes the body on each call, until it yields nothing.
+            ast::FunctionKind::Generator => ctx.line().put("return coroutine.wrap(function()"),
+            // A task, stepped by whoever awaits it or by the std scheduler.
+            ast::FunctionKind::Async => ctx
+                .line()
+                .put("return { thread = coroutine.create(function()"),
+        };
+        let ctx = self
+            .with_exits(Exits::default(), || self.visit_block(ctx.push(), body))?
+            .pop()
+            .unwrap()
+            .line();
+        Ok(match kind {
+            ast::FunctionKind::Async => ctx.put("end) };"),
+            _ => ctx.put("end);"),
+        })
+    }
+    /// Runs the (diverging) block if the variable is `nil`.
+    fn gen_nil_guard(&self, ctx: Builder, name: &str, block: &Script) -> Result {
+        let ctx = ctx.line().put(format!("if {name} == nil then")).push();
+        let ctx = self.visit_block(ctx, block)?;
+        Ok(ctx.pop().unwrap().line().put("end"))
+    }
+    /// Assigns the default value to the target, if it was left `nil`.
+    fn gen_default(&self, ctx: Builder, id: &Identifier, default: &ast::Expression) -> Result {
+        let ctx = ctx
+            .line()
+            .put(format!("if {} == nil then", id.0))
+            .push()
+            .line()
+            .put(format!("{} = ", id.0));
+        let ctx = self.visit_expression(ctx, default)?.put(";");
+        Ok(ctx.pop().unwrap().line().put("end"))
     }
 }
 
@@ -170,14 +1308,23 @@ impl Visitor for LuaEmitter {
     }
 
     fn visit_return(&self, ctx: Builder, stmt: &ast::Return) -> Result {
-        let ctx = ctx.line().put("return ");
+        if self.exits.get().returns == Exit::Enclosed {
+            return Err(VisitError(Box::new(ExitFromIfExpression("return"))));
+        }
+        if let ast::Expression::If(value) = &stmt.value {
+            return self.gen_if(ctx, value, ValueSink::Return);
+        }
+        if let Some((head, steps)) = optional_chain(&stmt.value) {
+            return self.gen_chain(ctx, head, &steps, ValueSink::Return);
+        }
+        let ctx = self.gen_return(ctx);
         let ctx = self.visit_expression(ctx, &stmt.value)?;
         Ok(ctx.put(";"))
     }
 
     fn visit_block_expression(&self, ctx: Builder, expr: &ast::Do) -> Result {
         let ctx = ctx.put("(function()").push();
-        let ctx = self.visit_block(ctx, &expr.body)?;
+        let ctx = self.with_exits(Exits::default(), || self.visit_block(ctx, &expr.body))?;
         let ctx = ctx.pop().unwrap().line().put("end)()");
         Ok(ctx)
     }
@@ -193,115 +1340,241 @@ impl Visitor for LuaEmitter {
     }
 
     fn visit_class(&self, ctx: Builder, stmt: &ast::Class) -> Result {
+        let name = stmt.name.0.clone();
         let ctx = ctx
             .line()
-            .put(format!("local {} = {{}};", stmt.name.0.clone()))
+            .put(format!("local {} = {{}};", name))
             .line()
-            .put(format!("{}.__meta__ = {{}};", stmt.name.0.clone()))
+            .put(format!("{}.__meta__ = {{}};", name));
+        // Static members are inherited from the parent class itself.
+        let ctx = if let Some(parent) = &stmt.parent {
+            ctx.line()
+                .put(format!("{}.__super__ = {};", name, parent.0))
+                .line()
+                .put(format!("{}.__meta__.__index = {};", name, parent.0))
+        } else {
+            ctx
+        };
+        let ctx = ctx
             .line()
             .put(format!(
-                "{}.__meta__.__call = function(self, struct)",
-                stmt.name.0.clone()
+                "{}.__meta__.__call = function(__class__, __struct__)",
+                name
             ))
-            .push()
+            .push();
+        // Constructor chaining: the parent builds the instance first.
+        let ctx = if let Some(parent) = &stmt.parent {
+            ctx.line().put(format!(
+                "__struct__ = {}.__meta__.__call(__class__, __struct__);",
+                parent.0
+            ))
+        } else {
+            ctx
+        };
+        // Then the declared fields are initialized. Defaults are evaluated
+        // for each instance, so mutable values are never shared. The
+        // constructor's own arguments use reserved names so defaults can't
+        // see them.
+        let ctx = stmt.fields.iter().try_fold(ctx, |ctx, field| {
+            let ast::ClassField::Let(f) = field else {
+                return Ok(ctx);
+            };
+            let field = match &f.target {
+                ast::AssignmentTarget::Identifier(e) => e.0.clone(),
+                ast::AssignmentTarget::Destructuring(_) => {
+                    return Err(VisitError(Box::new(DestructuredField(name.clone()))))
+                }
+            };
+            let ctx = ctx
+                .line()
+                .put(format!("if __struct__.{} == nil then", field))
+                .push()
+                .line();
+            let ctx = if let Some(value) = f.value.as_ref() {
+                let ctx = ctx.put(format!("__struct__.{} = ", field));
+                self.visit_expression(ctx, value)?.put(";")
+            } else {
+                ctx.put(format!(
+                    "error(\"Missing required field \\\"{}\\\" when constructing {}\", 2);",
+                    field, name
+                ))
+            };
+            Ok(ctx.pop().unwrap().line().put("end"))
+        })?;
+        let ctx = ctx
             .line()
-            .put("return setmetatable(struct, self.prototype.__meta__);")
+            .put("return setmetatable(__struct__, __class__.prototype.__meta__);")
             .pop()
             .unwrap()
             .line()
             .put("end;")
             .line()
-            .put(format!("{}.prototype = {{}};", stmt.name.0.clone()))
-            .line()
-            .put(format!(
-                "{}.prototype.__proto__ = {};",
-                stmt.name.0.clone(),
-                stmt.name.0.clone()
+            .put(format!("{}.prototype = {{}};", name));
+        // Instance lookups fall back to the parent's prototype.
+        let ctx = if let Some(parent) = &stmt.parent {
+            ctx.line().put(format!(
+                "setmetatable({}.prototype, {{ __index = {}.prototype }});",
+                name, parent.0
             ))
+        } else {
+            ctx
+        };
+        let ctx = ctx
             .line()
-            .put(format!(
-                "{}.prototype.__meta__ = {{}};",
-                stmt.name.0.clone()
-            ))
+            .put(format!("{}.prototype.__proto__ = {};", name, name))
+            .line()
+            .put(format!("{}.prototype.__meta__ = {{}};", name))
             .line()
             .put(format!(
                 "{}.prototype.__meta__.__index = {}.prototype;",
-                stmt.name.0.clone(),
-                stmt.name.0.clone()
+                name, name
             ))
             .line()
-            .put(format!(
-                "setmetatable({}, {}.__meta__);",
-                stmt.name.0.clone(),
-                stmt.name.0.clone()
-            ));
-        let ctx = stmt.fields.iter().fold(Ok(ctx), |ctx, field| {
-            let ctx = ctx?.line();
-            let ctx = match field {
-                ast::ClassField::Method(f) => {
-                    let is_self = if let Some(first) = f.arguments.first() {
-                        first.name.0 == "self"
-                    } else {
-                        false
-                    };
-                    let level = if is_self { ".prototype." } else { "." }.to_string();
-                    let ctx = ctx
-                        .put(stmt.name.0.clone())
-                        .put(level)
-                        .put(f.name.0.clone())
-                        .put(" = ");
-                    let arguments = f.arguments.clone();
-                    let ctx = self.visit_lambda(
-                        ctx,
-                        &ast::Lambda {
-                            arguments,
-                            body: ast::ScriptOrExpression::Script(f.body.clone()),
-                        },
-                    )?;
-                    let ctx = ctx.put(";");
-                    let ctx = f.decorators.iter().fold(Ok(ctx), |ctx, dec| {
-                        let ctx = ctx?.line();
-                        let ctx = self.visit_call(ctx, &dec.target)?;
-                        let fn_ref = if is_self {
-                            format!("{}.prototype.{}", stmt.name.0.clone(), f.name.0.clone())
+            .put(format!("setmetatable({}, {}.__meta__);", name, name));
+        // Overloaded operators are inherited too, metamethods are raw lookups.
+        let ctx = if let Some(parent) = &stmt.parent {
+            ctx.line()
+                .put(format!(
+                    "for key, value in pairs({}.prototype.__meta__) do",
+                    parent.0
+                ))
+                .push()
+                .line()
+                .put(format!(
+                    "if key ~= \"__index\" and key ~= \"__newindex\" then {}.prototype.__meta__[key] = value; end",
+                    name
+                ))
+                .pop()
+                .unwrap()
+                .line()
+                .put("end;")
+        } else {
+            ctx
+        };
+        // Accessors live in their own tables, chained to the parent ones.
+        let ctx = [
+            (
+                "__getters__",
+                stmt.fields
+                    .iter()
+                    .any(|f| matches!(f, ast::ClassField::Getter(_))),
+            ),
+            (
+                "__setters__",
+                stmt.fields
+                    .iter()
+                    .any(|f| matches!(f, ast::ClassField::Setter(_))),
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, declared)| *declared)
+        .fold(ctx, |ctx, (table, _)| match &stmt.parent {
+            Some(parent) => ctx.line().put(format!(
+                "{}.{} = setmetatable({{}}, {{ __index = {}.{} }});",
+                name, table, parent.0, table
+            )),
+            None => ctx.line().put(format!("{}.{} = {{}};", name, table)),
+        });
+        let ctx = stmt
+            .fields
+            .iter()
+            // Instance fields are initialized by the constructor.
+            .filter(|field| !matches!(field, ast::ClassField::Let(_)))
+            .try_fold(ctx, |ctx, field| {
+                let ctx = ctx.line();
+                let ctx = match field {
+                    ast::ClassField::Method(f) => {
+                        self.visit_class_method(ctx, stmt, f, !is_instance_method(f))?
+                    }
+                    ast::ClassField::StaticMethod(f) => {
+                        self.visit_class_method(ctx, stmt, f, true)?
+                    }
+                    ast::ClassField::Getter(f) => {
+                        let ctx = ctx.put(format!("{}.__getters__.{} = ", name, f.name.0));
+                        self.visit_method_body(ctx, stmt, f, false)?.put(";")
+                    }
+                    ast::ClassField::Setter(f) => {
+                        let ctx = ctx.put(format!("{}.__setters__.{} = ", name, f.name.0));
+                        self.visit_method_body(ctx, stmt, f, false)?.put(";")
+                    }
+                    ast::ClassField::Let(_) => unreachable!(),
+                    ast::ClassField::StaticLet(f) => {
+                        let ctx = match &f.target {
+                            ast::AssignmentTarget::Destructuring(_) => {
+                                return Err(VisitError(Box::new(DestructuredField(name.clone()))))
+                            }
+                            ast::AssignmentTarget::Identifier(e) => {
+                                ctx.put(format!("{}.{} = ", name, e.0))
+                            }
+                        };
+                        let ctx = if let Some(value) = f.value.as_ref() {
+                            self.visit_expression(ctx, value)?
                         } else {
-                            format!("{}.{}", stmt.name.0.clone(), f.name.0.clone())
+                            ctx.put("nil")
                         };
-                        let ctx = ctx.put(format!(
-                            "({}, \"{}\", {}, \"{}\", {{ is_static = {} }});",
-                            fn_ref,
-                            f.name.0.clone(),
-                            stmt.name.0.clone(),
-                            stmt.name.0.clone(),
-                            !is_self
-                        ));
-                        Ok(ctx)
-                    })?;
-                    ctx
-                }
-                ast::ClassField::Let(f) => {
-                    let ctx = match &f.target {
-                        ast::AssignmentTarget::Destructuring(_) => {
-                            panic!("Can't destructure that!")
-                        }
-                        ast::AssignmentTarget::Identifier(e) => ctx.put(format!(
-                            "{}.prototype.{} = ",
-                            stmt.name.0.clone(),
-                            e.0.clone()
-                        )),
-                    };
-                    let ctx = if let Some(value) = f.value.as_ref() {
-                        self.visit_expression(ctx, value)?
-                    } else {
-                        ctx.put("nil")
-                    };
-                    ctx.put(";")
+                        ctx.put(";")
+                    }
+                };
+                Ok(ctx)
+            })?;
+        // Operator methods are also the metamethods of the instances.
+        let ctx = stmt
+            .fields
+            .iter()
+            .filter_map(|field| match field {
+                ast::ClassField::Method(f) if is_instance_method(f) => {
+                    operator_metamethod(&f.name.0, f.arguments.len()).map(|meta| (meta, f))
                 }
-            };
-            Ok(ctx)
-        })?;
-        let ctx = stmt.decorators.iter().fold(Ok(ctx), |ctx, dec| {
-            let ctx = ctx?.line();
+                _ => None,
+            })
+            .fold(ctx, |ctx, (meta, f)| {
+                ctx.line().put(format!(
+                    "{}.prototype.__meta__.{} = {}.prototype.{};",
+                    name, meta, name, f.name.0
+                ))
+            });
+        let index_fn = generate_operator_function_name("[]".into());
+        let declares_index = stmt.fields.iter().any(|field| match field {
+            ast::ClassField::Method(f) => is_instance_method(f) && f.name.0 == index_fn,
+            ast::ClassField::Getter(_) => true,
+            _ => false,
+        });
+        let ctx = if declares_index {
+            instance_index(ctx, &name)
+        } else if stmt.parent.is_some() {
+            // The parent might have getters or overload the indexing operator.
+            let ctx = ctx
+                .line()
+                .put(format!(
+                    "if {0}.__getters__ ~= nil or {0}.prototype.{1} ~= nil then",
+                    name, index_fn
+                ))
+                .push();
+            instance_index(ctx, &name).pop().unwrap().line().put("end;")
+        } else {
+            ctx
+        };
+        let declares_setters = stmt
+            .fields
+            .iter()
+            .any(|field| matches!(field, ast::ClassField::Setter(_)));
+        let ctx = if declares_setters {
+            instance_newindex(ctx, &name)
+        } else if stmt.parent.is_some() {
+            let ctx = ctx
+                .line()
+                .put(format!("if {}.__setters__ ~= nil then", name))
+                .push();
+            instance_newindex(ctx, &name)
+                .pop()
+                .unwrap()
+                .line()
+                .put("end;")
+        } else {
+            ctx
+        };
+        let ctx = stmt.decorators.iter().try_fold(ctx, |ctx, dec| {
+            let ctx = ctx.line();
             let ctx = self.visit_call(ctx, &dec.target)?;
             let ctx = ctx.put(format!(
                 "({}, \"{}\");",
@@ -313,6 +1586,137 @@ impl Visitor for LuaEmitter {
         Ok(ctx)
     }
 
+    fn visit_enum(&self, ctx: Builder, stmt: &ast::Enum) -> Result {
+        let name = stmt.name.0.clone();
+        let ctx = ctx
+            .line()
+            .put(format!("local {} = {{}};", name))
+            .line()
+            .put(format!("{}.__meta__ = {{}};", name))
+            .line()
+            .put(format!("{}.prototype = {{}};", name))
+            .line()
+            .put(format!("{}.prototype.__proto__ = {};", name, name))
+            .line()
+            .put(format!("{}.prototype.__meta__ = {{}};", name))
+            .line()
+            .put(format!(
+                "{}.prototype.__meta__.__index = {}.prototype;",
+                name, name
+            ))
+            .line()
+            .put(format!("setmetatable({}, {}.__meta__);", name, name));
+        // The payload field names of each variant, in declaration order.
+        let ctx = ctx.line().put(format!("{}.__variants__ = {{", name)).push();
+        let ctx = stmt.variants.iter().fold(ctx, |ctx, variant| {
+            let fields = variant
+                .fields
+                .iter()
+                .flatten()
+                .map(|field| format!("\"{}\"", field.0))
+                .collect::<Vec<_>>()
+                .join(", ");
+            ctx.line()
+                .put(format!("{} = {{ {} }},", variant.name.0, fields))
+        });
+        let ctx = ctx
+            .pop()
+            .unwrap()
+            .line()
+            .put("};")
+            .line()
+            .put(format!(
+                "{}.prototype.__meta__.__tostring = function(self)",
+                name
+            ))
+            .push()
+            .line()
+            .put(format!("local fields = {}.__variants__[self.kind];", name))
+            .line()
+            .put("if #fields == 0 then")
+            .push()
+            .line()
+            .put("return self.kind;")
+            .pop()
+            .unwrap()
+            .line()
+            .put("end")
+            .line()
+            .put("local values = {};")
+            .line()
+            .put("for i, field in ipairs(fields) do values[i] = tostring(self[field]); end")
+            .line()
+            .put("return self.kind .. \"(\" .. table.concat(values, \", \") .. \")\";")
+            .pop()
+            .unwrap()
+            .line()
+            .put("end;")
+            .line()
+            .put(format!(
+                "{}.prototype.__meta__.__eq = function(left, right)",
+                name
+            ))
+            .push()
+            .line()
+            .put("if left.kind ~= right.kind then return false; end")
+            .line()
+            .put(format!(
+                "for _, field in ipairs({}.__variants__[left.kind]) do",
+                name
+            ))
+            .push()
+            .line()
+            .put("if left[field] ~= right[field] then return false; end")
+            .pop()
+            .unwrap()
+            .line()
+            .put("end")
+            .line()
+            .put("return true;")
+            .pop()
+            .unwrap()
+            .line()
+            .put("end;");
+        // Variants with payload are constructors, unit variants are values.
+        let ctx = stmt.variants.iter().fold(ctx, |ctx, variant| {
+            let kind = format!("kind = \"{}\"", variant.name.0);
+            match &variant.fields {
+                Some(fields) => {
+                    let args = fields
+                        .iter()
+                        .map(|field| field.0.clone())
+                        .collect::<Vec<_>>();
+                    let entries = std::iter::once(kind)
+                        .chain(args.iter().map(|arg| format!("{} = {}", arg, arg)))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    ctx.line()
+                        .put(format!(
+                            "{}.{} = function({})",
+                            name,
+                            variant.name.0,
+                            args.join(", ")
+                        ))
+                        .push()
+                        .line()
+                        .put(format!(
+                            "return setmetatable({{ {} }}, {}.prototype.__meta__);",
+                            entries, name
+                        ))
+                        .pop()
+                        .unwrap()
+                        .line()
+                        .put("end;")
+                }
+                None => ctx.line().put(format!(
+                    "{}.{} = setmetatable({{ {} }}, {}.prototype.__meta__);",
+                    name, variant.name.0, kind, name
+                )),
+            }
+        });
+        Ok(ctx)
+    }
+
     fn visit_fn(&self, ctx: Builder, stmt: &ast::Function) -> Result {
         let ctx = ctx
             .line()
@@ -322,11 +1726,12 @@ impl Visitor for LuaEmitter {
         let arg_names = stmt
             .arguments
             .iter()
-            .map(|a| {
+            .enumerate()
+            .map(|(i, a)| {
                 if a.spread {
                     "...".into()
                 } else {
-                    a.name.0.clone()
+                    argument_name(i, a)
                 }
             })
             .collect::<Vec<String>>()
@@ -342,10 +1747,11 @@ impl Visitor for LuaEmitter {
         } else {
             ctx
         };
-        let ctx = self.visit_block(ctx, &stmt.body)?;
+        let ctx = self.visit_argument_prologue(ctx, &stmt.arguments)?;
+        let ctx = self.gen_function_body(ctx, stmt.kind, &stmt.body)?;
         let ctx = ctx.pop().unwrap().line().put("end");
-        let ctx = stmt.decorators.iter().fold(Ok(ctx), |ctx, dec| {
-            let ctx = ctx?.line();
+        let ctx = stmt.decorators.iter().try_fold(ctx, |ctx, dec| {
+            let ctx = ctx.line();
             let ctx = self.visit_call(ctx, &dec.target)?;
             let ctx = ctx.put(format!(
                 "({}, \"{}\");",
@@ -361,6 +1767,12 @@ impl Visitor for LuaEmitter {
         let segment = self
             .visit_reference(ctx.clone_like(), &stmt.target)?
             .collect();
+        if let (None, ast::Expression::If(value)) = (&stmt.extra, &stmt.value) {
+            return self.gen_if(ctx, value, ValueSink::Assign(&segment));
+        }
+        if let (None, Some((head, steps))) = (&stmt.extra, optional_chain(&stmt.value)) {
+            return self.gen_chain(ctx, head, &steps, ValueSink::Assign(&segment));
+        }
         let ctx = ctx.line().put(segment).put(" = ");
         let ctx = if let Some(extra) = stmt.extra.as_ref() {
             let ast::Assignment { target, value, .. } = stmt.clone();
@@ -381,24 +1793,59 @@ impl Visitor for LuaEmitter {
 
     fn visit_declaration(&self, ctx: Builder, stmt: &ast::Let) -> Result {
         let ctx = match &stmt.target {
-            ast::AssignmentTarget::Destructuring(e) => {
-                self.gen_destruct(ctx, stmt.value.as_ref().unwrap(), e)?
-            }
+            ast::AssignmentTarget::Destructuring(e) => self.gen_destruct_else(
+                ctx,
+                stmt.value.as_ref().unwrap(),
+                e,
+                stmt.else_branch.as_ref(),
+            )?,
             ast::AssignmentTarget::Identifier(e) => {
-                let ctx = ctx.line().put("local ").put(e.0.clone());
-                let ctx = if let Some(value) = &stmt.value {
-                    let ctx = ctx.put(" = ");
-                    self.visit_expression(ctx, value)?
+                let ctx = if let Some(ast::Expression::If(value)) = &stmt.value {
+                    // The branches may read a previous binding of the same
+                    // name, so the new local is declared after they run.
+                    let depth = self.if_values.replace(self.if_values.get() + 1);
+                    let temp = match depth {
+                        0 => "__if_value__".to_string(),
+                        n => format!("__if_value_{n}__"),
+                    };
+                    let ctx = ctx.line().put(format!("local {temp};"));
+                    let ctx = self.gen_if(ctx, value, ValueSink::Declared(&temp));
+                    self.if_values.set(depth);
+                    ctx?.line().put(format!("local {} = {temp};", e.0))
+                } else if let Some((head, steps)) = stmt.value.as_ref().and_then(optional_chain) {
+                    self.gen_chain(ctx, head, &steps, ValueSink::Bind(&e.0))?
                 } else {
-                    ctx
+                    let ctx = ctx.line().put("local ").put(e.0.clone());
+                    let ctx = if let Some(value) = &stmt.value {
+                        let ctx = ctx.put(" = ");
+                        self.visit_expression(ctx, value)?
+                    } else {
+                        ctx
+                    };
+                    ctx.put(";")
                 };
-                ctx.put(";")
+                match &stmt.else_branch {
+                    Some(else_branch) => self.gen_nil_guard(ctx, &e.0, else_branch)?,
+                    None => ctx,
+                }
             }
         };
         Ok(ctx)
     }
 
     fn visit_expression_statement(&self, ctx: Builder, stmt: &ast::Expression) -> Result {
+        if is_break(stmt) {
+            if self.exits.get().breaks == Exit::Enclosed {
+                return Err(VisitError(Box::new(ExitFromIfExpression("break"))));
+            }
+            return Ok(self.gen_break(ctx));
+        }
+        if let ast::Expression::If(value) = stmt {
+            return self.gen_if(ctx, value, ValueSink::Discard);
+        }
+        if let Some((head, steps)) = optional_chain(stmt) {
+            return self.gen_chain(ctx, head, &steps, ValueSink::Discard);
+        }
         Ok(self.visit_expression(ctx.line(), stmt)?.put(";"))
     }
 
@@ -407,8 +1854,9 @@ impl Visitor for LuaEmitter {
         let arg_src = expr
             .arguments
             .iter()
-            .filter(|a| !a.spread)
-            .map(|a| a.name.0.clone())
+            .enumerate()
+            .filter(|(_, a)| !a.spread)
+            .map(|(i, a)| argument_name(i, a))
             .collect::<Vec<String>>();
         let spread = expr.arguments.iter().find(|a| a.spread);
         let ctx = ctx.put(arg_src.join(", "));
@@ -428,26 +1876,39 @@ impl Visitor for LuaEmitter {
         } else {
             ctx
         };
+        let ctx = self.visit_argument_prologue(ctx, &expr.arguments)?;
         let ctx = match &expr.body {
-            ast::ScriptOrExpression::Script(e) => self.visit_block(ctx, e)?,
-            ast::ScriptOrExpression::Expression(e) => self
-                .visit_expression(ctx.line().put("return "), e)
-                .map(|b| b.put(";"))?,
+            ast::ScriptOrExpression::Script(e) => self.gen_function_body(ctx, expr.kind, e)?,
+            ast::ScriptOrExpression::Expression(e) => {
+                // Expression bodies are never wrapped in a coroutine.
+                let outer_kind = self.function_kind.replace(ast::FunctionKind::Plain);
+                let ctx = self.visit_expression(ctx.line().put("return "), e);
+                self.function_kind.set(outer_kind);
+                ctx?.put(";")
+            }
         };
         Ok(ctx.pop().unwrap().line().put("end"))
     }
 
     fn visit_reference(&self, ctx: Builder, expr: &ast::MemberExpression) -> Result {
+        if expr.is_optional() {
+            let steps = expr.tail.iter().map(ChainStep::from).collect::<Vec<_>>();
+            return self.visit_chain(ctx, &expr.head, &steps);
+        }
         let ctx = self.visit_expression(ctx, &expr.head)?;
-        let ctx = expr.tail.iter().fold(Ok(ctx), |ctx, elem| {
-            self.generate_member_segment(self, ctx?, elem)
+        let ctx = expr.tail.iter().try_fold(ctx, |ctx, elem| {
+            self.generate_member_segment(self, ctx, elem)
         })?;
         Ok(ctx)
     }
 
     fn visit_use_statement(&self, ctx: Builder, expr: &ast::UseStatement) -> Result {
-        // let path = self.map_module_path(&expr.module);
         let path = expr.module.join(".");
+        self.check_module(&path)
+            .map_err(|err| VisitError(Box::new(err)))?;
+        if path == "std" {
+            self.record_std_usage(expr.expanded.as_ref());
+        }
         let tail = expr.module.last().unwrap();
         let ctx = if let Some(expand) = expr.expanded.as_ref() {
             let call = ast::CallSubExpression {
@@ -456,13 +1917,14 @@ impl Visitor for LuaEmitter {
                     tail: vec![],
                 }),
                 arguments: vec![ast::StringLiteral::expression_from_value(path.clone())],
+                optional: false,
             };
             let call = ast::CallExpression {
                 head: call,
                 tail: vec![],
             };
             let call = ast::Expression::Call(Box::new(call));
-            self.gen_destruct(ctx, &call, expand)?
+            self.gen_destruct_from(ctx, &call, expand, None, Some(&path))?
         } else {
             ctx.line()
                 .put(format!("local {} = require(\"{}\");", tail, path))
@@ -471,6 +1933,11 @@ impl Visitor for LuaEmitter {
     }
 
     fn visit_call(&self, ctx: Builder, expr: &ast::CallExpression) -> Result {
+        if expr.is_optional() {
+            if let Some(callee) = expr.head.callee.as_ref() {
+                return self.visit_chain(ctx, &callee.head, &call_chain(expr));
+            }
+        }
         let ctx = if let Some(callee) = expr.head.callee.clone() {
             let ctx = self.visit_expression(ctx, &callee.head)?;
             let ctx = callee
@@ -479,18 +1946,21 @@ impl Visitor for LuaEmitter {
                 .rev()
                 .skip(1)
                 .rev()
-                .fold(Ok(ctx), |ctx, elem| {
-                    let ctx = ctx?;
+                .try_fold(ctx, |ctx, elem| {
                     let ctx = match elem {
                         ast::MemberSegment::Computed(c) => {
                             let ctx = ctx.put("[");
-                            let ctx = self.visit_expression(ctx, &c)?;
+                            let ctx = self.visit_expression(ctx, c)?;
                             ctx.put("]")
                         }
                         ast::MemberSegment::Identifier(c) => ctx.put(".").put(c.0.clone()),
                         ast::MemberSegment::Dispatch(_) => {
                             panic!("Dispatch not allowed in this position")
                         }
+                        ast::MemberSegment::OptionalComputed(_)
+                        | ast::MemberSegment::OptionalIdentifier(_) => {
+                            unreachable!("Optional chains are emitted by visit_chain")
+                        }
                     };
                     Ok(ctx)
                 })?;
@@ -498,11 +1968,15 @@ impl Visitor for LuaEmitter {
                 match last {
                     ast::MemberSegment::Computed(c) => {
                         let ctx = ctx.put("[");
-                        let ctx = self.visit_expression(ctx, &c)?;
+                        let ctx = self.visit_expression(ctx, c)?;
                         ctx.put("]")
                     }
                     ast::MemberSegment::Identifier(c) => ctx.put(".").put(c.0.clone()),
                     ast::MemberSegment::Dispatch(c) => ctx.put(":").put(c.0.clone()),
+                    ast::MemberSegment::OptionalComputed(_)
+                    | ast::MemberSegment::OptionalIdentifier(_) => {
+                        unreachable!("Optional chains are emitted by visit_chain")
+                    }
                 }
             } else {
                 ctx
@@ -522,14 +1996,14 @@ impl Visitor for LuaEmitter {
             .arguments
             .iter()
             .skip(1)
-            .fold(Ok(ctx), |ctx, elem| {
-                let ctx = ctx?.put(", ");
+            .try_fold(ctx, |ctx, elem| {
+                let ctx = ctx.put(", ");
                 self.visit_expression(ctx, elem)
             })?;
         let ctx = ctx.put(")");
-        let ctx = expr.tail.iter().fold(Ok(ctx), |ctx, elem| match elem {
+        let ctx = expr.tail.iter().try_fold(ctx, |ctx, elem| match elem {
             ast::CallExpressionVariant::Call(c) => self.visit_call(
-                ctx?,
+                ctx,
                 &ast::CallExpression {
                     head: c.clone(),
                     tail: vec![],
@@ -537,12 +2011,16 @@ impl Visitor for LuaEmitter {
             ),
             ast::CallExpressionVariant::Member(m) => match m {
                 ast::MemberSegment::Computed(c) => {
-                    let ctx = ctx?.put("[");
-                    let ctx = self.visit_expression(ctx, &c)?;
+                    let ctx = ctx.put("[");
+                    let ctx = self.visit_expression(ctx, c)?;
                     Ok(ctx.put("]"))
                 }
-                ast::MemberSegment::Identifier(i) => Ok(ctx?.put(".").put(i.0.clone())),
-                ast::MemberSegment::Dispatch(i) => Ok(ctx?.put(":").put(i.0.clone())),
+                ast::MemberSegment::Identifier(i) => Ok(ctx.put(".").put(i.0.clone())),
+                ast::MemberSegment::Dispatch(i) => Ok(ctx.put(":").put(i.0.clone())),
+                ast::MemberSegment::OptionalComputed(_)
+                | ast::MemberSegment::OptionalIdentifier(_) => {
+                    unreachable!("Optional chains are emitted by visit_chain")
+                }
             },
         })?;
         Ok(ctx)
@@ -551,7 +2029,7 @@ impl Visitor for LuaEmitter {
     fn visit_tuple(&self, ctx: Builder, expr: &ast::Tuple) -> Result {
         let ctx = ctx.put("{");
         let ctx = if let Some(first) = expr.0.first().as_ref() {
-            let ctx = ctx.put(format!("_0 = "));
+            let ctx = ctx.put("_0 = ");
             self.visit_expression(ctx, first)?
         } else {
             ctx
@@ -560,8 +2038,7 @@ impl Visitor for LuaEmitter {
             .0
             .iter()
             .skip(1)
-            .fold(Ok((ctx, 1_u16)), |ctx, value| {
-                let (ctx, i) = ctx?;
+            .try_fold((ctx, 1_u16), |(ctx, i), value| {
                 let ctx = ctx.put(format!(", _{} = ", i));
                 let ctx = self.visit_expression(ctx, value)?;
                 Ok((ctx, i + 1))
@@ -593,10 +2070,13 @@ impl Visitor for LuaEmitter {
             ctx
         };
         let numeric_string = match expr.value {
-            ast::NumberVariant::Float(e) => e.to_string(),
+            // Debug keeps the fraction ("1.0"), so Lua won't read it as an integer.
+            ast::NumberVariant::Float(e) => format!("{:?}", e),
             ast::NumberVariant::Integer(e) => e.to_string(),
-            ast::NumberVariant::Hexadecimal(e) => format!("0x{:x}", e),
-            ast::NumberVariant::Binary(e) => e.to_string(),
+            // Lua has no octal nor binary literals, hexadecimal keeps the bits readable.
+            ast::NumberVariant::Hexadecimal(e)
+            | ast::NumberVariant::Octal(e)
+            | ast::NumberVariant::Binary(e) => format!("0x{:x}", e),
             ast::NumberVariant::Character(e) => e.to_string(),
         };
         let ctx = ctx.put(numeric_string);
@@ -605,7 +2085,7 @@ impl Visitor for LuaEmitter {
         } else {
             ctx
         };
-        return Ok(ctx);
+        Ok(ctx)
     }
 
     fn visit_string(&self, ctx: Builder, expr: &ast::StringLiteral) -> Result {
@@ -615,10 +2095,7 @@ impl Visitor for LuaEmitter {
         } else {
             ctx
         };
-        let ctx = ctx
-            .put("\"")
-            .put(escape_string(expr.value.clone()))
-            .put("\"");
+        let ctx = ctx.put(quote_string(&expr.value));
         let ctx = if expr.prefix.is_some() {
             ctx.put(")")
         } else {
@@ -627,6 +2104,30 @@ impl Visitor for LuaEmitter {
         Ok(ctx)
     }
 
+    fn visit_interpolated_string(&self, ctx: Builder, expr: &ast::InterpolatedString) -> Result {
+        let ctx = if let Some(prefix) = &expr.prefix {
+            self.visit_identifier(ctx, prefix)?
+        } else {
+            ctx
+        };
+        let ctx = ctx.put("(");
+        let ctx = expr
+            .parts
+            .iter()
+            .enumerate()
+            .try_fold(ctx, |ctx, (i, part)| {
+                let ctx = if i > 0 { ctx.put(" .. ") } else { ctx };
+                match part {
+                    ast::StringPart::Literal(value) => Ok(ctx.put(quote_string(value))),
+                    ast::StringPart::Expression(value) => {
+                        let ctx = self.visit_expression(ctx.put("tostring("), value)?;
+                        Ok(ctx.put(")"))
+                    }
+                }
+            })?;
+        Ok(ctx.put(")"))
+    }
+
     fn visit_unit(&self, ctx: Builder) -> Result {
         Ok(ctx.put("nil"))
     }
@@ -712,8 +2213,8 @@ impl Visitor for LuaEmitter {
         let ctx = self.visit_expression(ctx, &expr.condition)?;
         let ctx = ctx.put(" then").push();
         let ctx = self.visit_block(ctx, &expr.body)?;
-        let ctx = expr.branches.iter().fold(Ok(ctx), |ctx, (c, s)| {
-            let ctx = ctx?.pop().unwrap().line().put("elseif ");
+        let ctx = expr.branches.iter().try_fold(ctx, |ctx, (c, s)| {
+            let ctx = ctx.pop().unwrap().line().put("elseif ");
             let ctx = self.visit_expression(ctx, c)?;
             let ctx = ctx.put(" then").push();
             let ctx = self.visit_block(ctx, s)?;
@@ -729,6 +2230,50 @@ impl Visitor for LuaEmitter {
         Ok(ctx)
     }
 
+    fn visit_if_expression(&self, ctx: Builder, expr: &ast::IfExpression) -> Result {
+        let ctx = ctx.put("(function()").push();
+        let exits = Exits {
+            returns: Exit::Enclosed,
+            breaks: Exit::Enclosed,
+        };
+        let ctx = self.with_exits(exits, || self.gen_if(ctx, expr, ValueSink::Return))?;
+        Ok(ctx.pop().unwrap().line().put("end)()"))
+    }
+
+    fn visit_try(&self, ctx: Builder, stmt: &ast::Try) -> Result {
+        self.gen_try(
+            ctx,
+            |ctx| self.visit_block(ctx, &stmt.body),
+            stmt.catch.as_ref(),
+            |ctx| match &stmt.finally {
+                Some(finally) => {
+                    let ctx = ctx.line().put("do").push();
+                    let ctx = self.visit_block(ctx, finally)?;
+                    Ok(ctx.pop().unwrap().line().put("end"))
+                }
+                None => Ok(ctx),
+            },
+        )
+    }
+
+    fn visit_defer(&self, ctx: Builder, stmt: &ast::Defer) -> Result {
+        let ctx = ctx
+            .line()
+            .put("__defers__[#__defers__ + 1] = function()")
+            .push();
+        let ctx = self.gen_if_value(ctx, &stmt.value, ValueSink::Discard)?;
+        Ok(ctx.pop().unwrap().line().put("end;"))
+    }
+
+    // Blocks with defer statements run protected, like a try block whose
+    // finally calls the deferred functions in reverse order.
+    fn visit_block(&self, ctx: Builder, script: &Script) -> Result {
+        let top_level = self.top_level.replace(false);
+        let result = self.gen_block(ctx, script, top_level);
+        self.top_level.set(top_level);
+        result
+    }
+
     fn visit_table(&self, ctx: Builder, expr: &ast::Table) -> Result {
         let ctx = ctx.put("{");
         let ctx = if let Some((k, v)) = expr.key_values.first() {
@@ -739,7 +2284,7 @@ impl Visitor for LuaEmitter {
                 }
                 ast::TableKeyExpression::Expression(k) => {
                     let ctx = ctx.put("[");
-                    let ctx = self.visit_expression(ctx, &k)?.put("] = ");
+                    let ctx = self.visit_expression(ctx, k)?.put("] = ");
                     self.visit_expression(ctx, &v.clone().unwrap())
                 }
                 ast::TableKeyExpression::Implicit(k) => {
@@ -754,8 +2299,8 @@ impl Visitor for LuaEmitter {
             .key_values
             .iter()
             .skip(1)
-            .fold(Ok(ctx), |ctx, (k, v)| {
-                let ctx = ctx?.put(", ");
+            .try_fold(ctx, |ctx, (k, v)| {
+                let ctx = ctx.put(", ");
                 match k {
                     ast::TableKeyExpression::Identifier(k) => {
                         let ctx = ctx.put(k.0.clone()).put(" = ");
@@ -763,7 +2308,7 @@ impl Visitor for LuaEmitter {
                     }
                     ast::TableKeyExpression::Expression(k) => {
                         let ctx = ctx.put("[");
-                        let ctx = self.visit_expression(ctx, &k)?.put("] = ");
+                        let ctx = self.visit_expression(ctx, k)?.put("] = ");
                         self.visit_expression(ctx, &v.clone().unwrap())
                     }
                     ast::TableKeyExpression::Implicit(k) => {
@@ -782,8 +2327,8 @@ impl Visitor for LuaEmitter {
         } else {
             ctx
         };
-        let ctx = expr.expressions.iter().skip(1).fold(Ok(ctx), |ctx, v| {
-            let ctx = ctx?.put(", ");
+        let ctx = expr.expressions.iter().skip(1).try_fold(ctx, |ctx, v| {
+            let ctx = ctx.put(", ");
             let ctx = self.visit_expression(ctx, v)?;
             Ok(ctx)
         })?;
@@ -800,14 +2345,14 @@ impl Visitor for LuaEmitter {
                     "__destructured_iterator_target__".into(),
                 ));
                 let ctx = self.gen_destruct(ctx, &dt, e)?;
-                let ctx = self.visit_block(ctx, &expr.body)?;
+                let ctx = self.gen_loop_body(ctx, &expr.body)?;
                 ctx.pop().unwrap().line().put("end")
             }
             ast::AssignmentTarget::Identifier(e) => {
                 let ctx = ctx.line().put(format!("for {} in ", e.0.clone()));
                 let ctx = self.visit_expression(ctx, &expr.target)?;
                 let ctx = ctx.put(" do").push();
-                let ctx = self.visit_block(ctx, &expr.body)?;
+                let ctx = self.gen_loop_body(ctx, &expr.body)?;
                 ctx.pop().unwrap().line().put("end")
             }
         };
@@ -820,7 +2365,7 @@ impl Visitor for LuaEmitter {
                 let ctx = ctx.line().put("while ");
                 let ctx = self.visit_expression(ctx, e)?;
                 let ctx = ctx.put(" do").push();
-                let ctx = self.visit_block(ctx, &expr.body)?;
+                let ctx = self.gen_loop_body(ctx, &expr.body)?;
                 ctx.pop().unwrap().line().put("end")
             }
             ast::ExpressionOrLet::Let(e) => {
@@ -828,7 +2373,7 @@ impl Visitor for LuaEmitter {
                     let ctx = ctx.line().put("do").push();
                     let ctx = self.visit_declaration(ctx, e)?;
                     let ctx = ctx.line().put(format!("while {} do", id.0.clone())).push();
-                    let ctx = self.visit_block(ctx, &expr.body)?;
+                    let ctx = self.gen_loop_body(ctx, &expr.body)?;
                     let ctx = self.visit_assignment(
                         ctx,
                         &ast::Assignment {
@@ -856,6 +2401,28 @@ impl Visitor for LuaEmitter {
         Ok(ctx)
     }
 
+    fn visit_yield(&self, ctx: Builder, expr: &ast::Yield) -> Result {
+        if self.function_kind.get() == ast::FunctionKind::Plain {
+            return Err(VisitError(Box::new(YieldOutsideCoroutine)));
+        }
+        let ctx = ctx.put("coroutine.yield(");
+        let ctx = match &expr.value {
+            Some(value) => self.visit_expression(ctx, value)?,
+            None => ctx,
+        };
+        Ok(ctx.put(")"))
+    }
+
+    fn visit_await(&self, ctx: Builder, expr: &ast::Await) -> Result {
+        if self.function_kind.get() != ast::FunctionKind::Async {
+            return Err(VisitError(Box::new(AwaitOutsideAsync)));
+        }
+        self.uses_await.set(true);
+        let ctx = ctx.put("__saturnus_await__(");
+        let ctx = self.visit_expression(ctx, &expr.value)?;
+        Ok(ctx.put(")"))
+    }
+
     fn visit_spread(&self, ctx: Builder, expr: &ast::SpreadExpression) -> Result {
         let ctx = ctx.put("__saturnus_spread__(");
         let ctx = self.visit_expression(ctx, &expr.expression)?;
@@ -865,7 +2432,7 @@ impl Visitor for LuaEmitter {
 
     fn visit_loop(&self, ctx: Builder, expr: &ast::Loop) -> Result {
         let ctx = ctx.line().put("while true do").push();
-        let ctx = self.visit_block(ctx, &expr.body)?;
+        let ctx = self.gen_loop_body(ctx, &expr.body)?;
         let ctx = ctx.pop().unwrap().line().put("end");
         Ok(ctx)
     }
@@ -881,6 +2448,45 @@ impl Visitor for LuaEmitter {
             .put("-- WARNING! Changes may be discarded at any moment!")
             .line()
             .put("local __saturnus_spread__ = table.unpack or unpack; -- Lua polyfill");
-        self.visit_block(ctx, script)
+        let exports = self.module_exports(script);
+        if !exports.is_empty() && returns(script) {
+            return Err(VisitError(Box::new(ReturnWithExports)));
+        }
+        // The exports are returned by the body itself, as top level defers
+        // move it into a protected function along with its locals.
+        let mut script = script.clone();
+        if !exports.is_empty() {
+            let key_values = exports
+                .into_iter()
+                .map(|id| {
+                    let value = ast::Expression::Identifier(id.clone());
+                    (ast::TableKeyExpression::Identifier(id), Some(value))
+                })
+                .collect();
+            let value = ast::Expression::Table(ast::Table { key_values });
+            script
+                .statements
+                .push(ast::Statement::Return(ast::Return { value }));
+        }
+        self.uses_try.set(false);
+        self.uses_await.set(false);
+        self.top_level.set(true);
+        let body = self.visit_block(ctx.clone_like(), &script)?;
+        // Only scripts that await carry the task stepping helper.
+        let ctx = if self.uses_await.get() {
+            AWAIT_HELPER
+                .lines()
+                .fold(ctx, |ctx, line| ctx.line().put(line))
+        } else {
+            ctx
+        };
+        let ctx = if self.uses_try.get() {
+            TRY_HELPER
+                .lines()
+                .fold(ctx, |ctx, line| ctx.line().put(line))
+        } else {
+            ctx
+        };
+        Ok(ctx._and(body))
     }
 }
@@ -1,29 +1,30 @@
 use std::{
-    fs::{self, File},
+    fs::File,
     io::Write,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use clap::Parser;
-use code::info::InputFileInfo;
-use errors::report_error;
-use lua::{helpers::generate_module_chunk, visitor::LuaEmitter};
-use parser::Script;
-use runtime::RuntimeError;
-
-use crate::code::{ast_visitor::Visitor, builder::Builder};
-
-#[cfg(test)]
-#[macro_use]
-extern crate spectral;
-
-mod code;
-mod errors;
-mod lua;
-mod parser;
-pub mod runtime;
-#[cfg(test)]
-mod tests;
+use clap::{Parser, Subcommand};
+use saturnus::{
+    code::{
+        ast_visitor::{VisitError, Visitor},
+        builder::Builder,
+        info::InputFileInfo,
+        scope_analysis::{ScopeAnalyzer, Severity, UndeclaredNames},
+    },
+    engine::{install_resolver, FileSystemResolver, Sandbox},
+    errors::report_error,
+    lint::{lint, Level, LintConfig, Rule},
+    lua::{
+        self,
+        bundle::Bundler,
+        helpers::{generate_module_chunk, long_bracket},
+        visitor::LuaEmitter,
+    },
+    parser::{self, Script},
+    runtime::{precompile_std, precompile_std_for, RuntimeError, StdUsage, STD_SRC},
+};
 
 #[derive(Parser, Clone)]
 #[command(name = "Saturnus")]
@@ -33,7 +34,10 @@ mod tests;
     about = "Saturnus: A modern language that compiles to Lua",
     long_about = None // "Saturnus is a programming language that aims to have a simplified mix of Rust programming language and Lua"
 )]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(
         short,
         long,
@@ -42,8 +46,8 @@ struct Args {
     output: Option<String>,
     #[arg(long, short)]
     verbose: bool,
-    #[arg(help = "The input file to evaluate and/or compile")]
-    input: String,
+    #[arg(required = true, help = "The input file to evaluate and/or compile")]
+    input: Option<String>,
     #[arg(short, long, help = "Compiles the Saturnus script")]
     compile: bool,
     #[arg(
@@ -76,6 +80,11 @@ struct Args {
         help = "The amount of space characters to use in each tab"
     )]
     indent: usize,
+    #[arg(
+        long,
+        help = "Bundles the modules reached by use statements into a single output"
+    )]
+    bundle: bool,
     #[arg(long, help = "Skips the std library")]
     no_std: bool,
     // Now std is inlined atop the entry point!
@@ -92,10 +101,116 @@ struct Args {
         help = "Additional module root paths to load"
     )]
     modules: Vec<String>,
+    #[arg(
+        long,
+        help = "Warns about modules not found in the module roots, instead of failing, and leaves them to Lua (Eg: native modules)"
+    )]
+    allow_unresolved: bool,
+    #[arg(
+        short = 'g',
+        long = "global",
+        help = "Globals provided by the host, readable without a declaration"
+    )]
+    globals: Vec<String>,
+    #[arg(
+        long,
+        help = "Fails the compilation when undeclared names are read, instead of warning"
+    )]
+    deny_undeclared: bool,
     #[arg(long, help = "Extracts the STD library compiled")]
     extract_std_compiled: Option<PathBuf>,
     #[arg(long, help = "Extracts the STD library raw (Saturnus code)")]
     extract_std_raw: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Evaluates the script in a sandbox, without io, os.execute nor load"
+    )]
+    sandbox: bool,
+    #[arg(long, help = "Sandbox: maximum amount of Lua instructions to run")]
+    max_instructions: Option<u64>,
+    #[arg(long, help = "Sandbox: maximum Lua memory in bytes")]
+    max_memory: Option<usize>,
+    #[arg(long, help = "Sandbox: maximum evaluation time in milliseconds")]
+    timeout: Option<u64>,
+}
+
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Checks the scripts against the lint rules, without running them
+    Lint(LintArgs),
+}
+
+#[derive(clap::Args, Clone)]
+struct LintArgs {
+    #[arg(required = true, help = "The Saturnus files to check")]
+    inputs: Vec<String>,
+    #[arg(short = 'A', long = "allow", help = "Rules that are not checked")]
+    allow: Vec<String>,
+    #[arg(short = 'W', long = "warn", help = "Rules reported as warnings")]
+    warn: Vec<String>,
+    #[arg(short = 'D', long = "deny", help = "Rules that fail the check")]
+    deny: Vec<String>,
+    #[arg(
+        short = 'g',
+        long = "global",
+        help = "Globals provided by the host, readable without a declaration"
+    )]
+    globals: Vec<String>,
+}
+
+impl LintArgs {
+    /// The configuration requested from the command line, denied rules
+    /// taking precedence.
+    fn get_config(&self) -> Result<LintConfig, String> {
+        let levels = [
+            (&self.allow, Level::Allow),
+            (&self.warn, Level::Warn),
+            (&self.deny, Level::Deny),
+        ];
+        let mut config = LintConfig::new();
+        for (rules, level) in levels {
+            for name in rules.iter() {
+                let rule = Rule::from_name(name).ok_or(format!("Unknown lint rule '{name}'"))?;
+                config = config.level(rule, level);
+            }
+        }
+        Ok(self
+            .globals
+            .iter()
+            .fold(config, |config, name| config.allow_global(name.clone())))
+    }
+}
+
+impl Args {
+    /// The indentation of the generated code.
+    fn get_indent(&self) -> String {
+        if self.use_tabs {
+            "\t".to_string()
+        } else {
+            " ".repeat(self.indent)
+        }
+    }
+    /// The sandbox requested from the command line, if any.
+    fn get_sandbox(&self) -> Option<Sandbox> {
+        if !self.sandbox
+            && self.max_instructions.is_none()
+            && self.max_memory.is_none()
+            && self.timeout.is_none()
+        {
+            return None;
+        }
+        let mut sandbox = Sandbox::new();
+        if let Some(limit) = self.max_instructions {
+            sandbox = sandbox.instruction_limit(limit);
+        }
+        if let Some(limit) = self.max_memory {
+            sandbox = sandbox.memory_limit(limit);
+        }
+        if let Some(limit) = self.timeout {
+            sandbox = sandbox.timeout(Duration::from_millis(limit));
+        }
+        Some(sandbox)
+    }
 }
 
 fn get_default_output(str: &Path) -> String {
@@ -112,48 +227,68 @@ struct CompilationOptions {
     out_path: String,
 }
 
-// fn scrap_modules(map: &mut HashMap<String, PathBuf>, roots: &Vec<String>) {
-//     let re = regex::Regex::new(r"\.saturn$").unwrap();
-//     for root in roots.iter() {
-//         for entry in glob::glob(format!("{}/**/*.saturn", root).as_str()).unwrap() {
-//             let entry: PathBuf = entry.unwrap();
-//             let mod_name = entry
-//                 .iter()
-//                 .skip(1)
-//                 .map(|p| p.to_str().unwrap().to_owned())
-//                 .collect::<Vec<String>>()
-//                 .join(".");
-//             let mod_name = re.replace_all(mod_name.as_str(), "");
-//             map.insert(mod_name.to_string(), entry);
-//         }
-//     }
-// }
-
-const STD_SRC: &'static str = include_str!("assets/std.saturn");
+/// Looks up `use` statements in the `--mod` roots, then next to the input.
+fn module_resolver(options: &CompilationOptions) -> FileSystemResolver {
+    let here = Path::new(&options.in_path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    FileSystemResolver::new(
+        options
+            .args
+            .modules
+            .iter()
+            .map(PathBuf::from)
+            .chain([here.to_path_buf()]),
+    )
+}
 
-fn precompile_std(compiler: &dyn Visitor) -> Result<(String, md5::Digest), RuntimeError> {
-    // Precompile STD
-    let std_src = STD_SRC;
-    let std_src = Script::parse(std_src.to_owned()).unwrap();
-    let std_src = compiler
-        .visit_script(Builder::new("  "), &std_src)
-        .unwrap()
-        .collect();
-    let crc = md5::compute(std_src.as_bytes());
-    Ok((std_src, crc))
+/// Reports reads of undeclared names, failing if they are denied.
+fn check_scopes(script: &Script, in_path: &str, args: &Args) -> Result<(), RuntimeError> {
+    let severity = if args.deny_undeclared {
+        Severity::Error
+    } else {
+        Severity::Warning
+    };
+    let diagnostics = ScopeAnalyzer::new()
+        .allow_globals(args.globals.iter().cloned())
+        .severity(severity)
+        .analyze(script)
+        .map_err(RuntimeError::CompilationError)?;
+    if severity == Severity::Error && !diagnostics.is_empty() {
+        let err = VisitError(Box::new(UndeclaredNames(diagnostics)));
+        return Err(RuntimeError::CompilationError(err));
+    }
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{}: {}", in_path, diagnostic);
+    }
+    Ok(())
 }
 
 fn compile_main(
     script: &Script,
-    compiler: &dyn Visitor,
+    compiler: &LuaEmitter,
     args: &Args,
 ) -> Result<String, RuntimeError> {
     let mut src = compiler
-        .visit_script(Builder::new("  "), &script)
-        .map_err(|err| RuntimeError::CompilationError(err))?
+        .visit_script(Builder::new(args.get_indent()), script)
+        .map_err(RuntimeError::CompilationError)?
         .collect();
+    if let Some(resolver) = compiler.resolver.clone().filter(|_| args.bundle) {
+        let modules = Bundler::new(resolver, args.get_indent()).bundle(compiler)?;
+        src = format!("{modules}\n{src}");
+    }
+    for module in compiler.unresolved_modules.borrow().iter() {
+        eprintln!("warning: {module}");
+    }
     if !args.no_std {
-        let (std_src, _) = precompile_std(compiler)?;
+        // Modules compiled on their own may use any part of std.
+        let usage = if args.bundle || compiler.used_modules.borrow().is_empty() {
+            compiler.std_usage.borrow().clone()
+        } else {
+            StdUsage::All
+        };
+        let (std_src, _) = precompile_std_for(compiler, &usage)?;
         src = format!(
             "{}\n{}",
             generate_module_chunk(&"std".into(), &std_src),
@@ -163,21 +298,56 @@ fn compile_main(
     Ok(src)
 }
 
-fn runtime_eval(script: &Script, compiler: &dyn Visitor, args: &Args) -> Result<(), RuntimeError> {
+/// Lets Lua find the plain `.lua` modules under the module roots too.
+fn add_lua_roots(ctx: rlua::Context, resolver: &FileSystemResolver) -> rlua::Result<()> {
+    let package: rlua::Table = ctx.globals().get("package")?;
+    let path: String = package.get("path")?;
+    let roots = resolver.roots.iter().flat_map(|root| {
+        [
+            root.join("?.lua").to_string_lossy().to_string(),
+            root.join("?")
+                .join("init.lua")
+                .to_string_lossy()
+                .to_string(),
+        ]
+    });
+    let path = roots.chain([path]).collect::<Vec<_>>().join(";");
+    package.set("path", path)
+}
+
+fn runtime_eval(script: &Script, compiler: &LuaEmitter, args: &Args) -> Result<(), RuntimeError> {
     let src = compile_main(script, compiler, args)?;
-    let lua = rlua::Lua::new();
+    let sandbox = args.get_sandbox();
+    let lua = match &sandbox {
+        Some(sandbox) => {
+            let lua = sandbox.create_lua();
+            sandbox.guard(&lua);
+            lua
+        }
+        None => rlua::Lua::new(),
+    };
     lua.context(move |ctx| -> rlua::Result<()> {
-        ctx.load(&src).eval()?;
+        // Modules are compiled when required, unless bundled already.
+        if let Some(resolver) = compiler.resolver.clone() {
+            add_lua_roots(ctx, &resolver)?;
+            install_resolver(ctx, resolver, &args.get_indent())?;
+        }
+        ctx.load(&src).exec()?;
         Ok(())
     })
-    .map_err(|err| RuntimeError::EvaluationError(err))?;
+    .map_err(|err| match &sandbox {
+        Some(sandbox) => sandbox.map_error(err),
+        None => RuntimeError::EvaluationError(err),
+    })?;
     Ok(())
 }
 
-fn try_run(options: CompilationOptions, input: String, indent: String) -> Result<(), RuntimeError> {
-    let compiler = lua::visitor::LuaEmitter::new(InputFileInfo {
+fn try_run(options: CompilationOptions, input: String) -> Result<(), RuntimeError> {
+    let compiler = LuaEmitter::new(InputFileInfo {
         full_path: PathBuf::from(&options.in_path),
-    });
+    })
+    .with_resolver(module_resolver(&options))
+    .with_unresolved_allowed(options.args.allow_unresolved);
 
     // We won't pop out that pesky "std.lua" file anymore!
     // if !options.args.no_std {
@@ -191,14 +361,12 @@ fn try_run(options: CompilationOptions, input: String, indent: String) -> Result
     //     }
     // }
 
-    // scrap_modules(&mut compiler.module_mapping, &options.args.modules);
-
     if options.args.dump_saturnus {
         println!("{input}");
         return Ok(());
     }
 
-    let script = parser::Script::parse(input).map_err(|err| RuntimeError::ParseError(err))?;
+    let script = parser::Script::parse(input).map_err(RuntimeError::ParseError)?;
 
     let CompilationOptions {
         args,
@@ -206,6 +374,8 @@ fn try_run(options: CompilationOptions, input: String, indent: String) -> Result
         in_path,
     } = options;
 
+    check_scopes(&script, &in_path, &args)?;
+
     if args.compile {
         if args.verbose {
             println!("Compiling {:?}...", in_path);
@@ -225,14 +395,57 @@ fn try_run(options: CompilationOptions, input: String, indent: String) -> Result
     Ok(())
 }
 
+/// Lints each file, returning false if any could not be parsed or broke a
+/// denied rule.
+fn lint_files(args: &LintArgs) -> bool {
+    let config = match args.get_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return false;
+        }
+    };
+    let mut passed = true;
+    for path in args.inputs.iter() {
+        let input = match std::fs::read_to_string(path) {
+            Ok(input) => input,
+            Err(err) => {
+                eprintln!("Could not read {path}: {err}");
+                passed = false;
+                continue;
+            }
+        };
+        let lints = Script::parse(input.clone())
+            .map_err(|err| report_error(path.clone(), input.clone(), err))
+            .and_then(|script| lint(&script, &config).map_err(|err| err.0.to_string()));
+        match lints {
+            Ok(lints) => {
+                for lint in lints.iter() {
+                    let (line, col) = lint.line_col(&input);
+                    eprintln!("{path}:{line}:{col}: {lint}");
+                }
+                passed &= lints.iter().all(|lint| lint.level != Level::Deny);
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
 fn main() {
     // Configure environment
     let args = Args::parse();
-    let indent = if args.use_tabs {
-        "\t".to_string()
-    } else {
-        " ".repeat(args.indent)
-    };
+    if let Some(Command::Lint(lint)) = &args.command {
+        if !lint_files(lint) {
+            eprintln!("Lint failed");
+            std::process::exit(-1);
+        }
+        return;
+    }
+    let input_path = args.input.clone().expect("The input is required");
     use std::fs::read_to_string;
 
     {
@@ -249,7 +462,7 @@ fn main() {
     }
 
     // Read input files
-    let in_path = Path::new(&args.input);
+    let in_path = Path::new(&input_path);
     let out_path = args.clone().output.unwrap_or(get_default_output(in_path));
     let input = read_to_string(in_path).unwrap();
 
@@ -258,22 +471,34 @@ fn main() {
         in_path: in_path.to_str().unwrap().to_owned(),
         out_path: out_path.to_owned(),
     };
-    match try_run(options, input.clone(), indent.clone()) {
+    match try_run(options, input.clone()) {
         Ok(_) => (),
         Err(err) => match err {
             RuntimeError::EvaluationError(err) => eprintln!("{}", err),
             RuntimeError::ParseError(err) => {
-                let err = report_error(args.input.clone(), input.clone(), err);
+                let err = report_error(input_path.clone(), input.clone(), err);
                 if args.compile && !args.print {
                     let mut out_file = File::create(out_path).unwrap();
-                    let output = format!("error[=====[{}]=====]", err);
+                    let output = format!("error{}", long_bracket(&err.to_string()));
                     out_file.write_all(output.as_bytes()).unwrap();
                 }
                 eprintln!("{}\nCompilation failed", err);
                 std::process::exit(-1);
             }
             RuntimeError::CompilationError(err) => {
-                eprintln!("{:?}", err);
+                eprintln!("{}\nCompilation failed", err.0);
+                std::process::exit(-1);
+            }
+            RuntimeError::InstructionLimitExceeded(limit) => {
+                eprintln!("Aborted: the script ran more than {limit} instructions");
+                std::process::exit(-1);
+            }
+            RuntimeError::MemoryLimitExceeded(limit) => {
+                eprintln!("Aborted: the script used more than {limit} bytes of memory");
+                std::process::exit(-1);
+            }
+            RuntimeError::TimeoutExceeded(limit) => {
+                eprintln!("Aborted: the script ran for longer than {limit:?}");
                 std::process::exit(-1);
             }
         },
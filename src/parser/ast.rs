@@ -10,6 +10,10 @@ pub struct Argument {
     pub name: Identifier,
     pub spread: bool,
     pub decorators: Vec<Decorator>,
+    /// Value taken when the argument is `nil`.
+    pub default: Option<Expression>,
+    /// Unpacks the argument into the targets, instead of binding `name`.
+    pub pattern: Option<Destructuring>,
 }
 
 #[derive(Debug, Clone)]
@@ -18,6 +22,18 @@ pub struct Function {
     pub arguments: Vec<Argument>,
     pub decorators: Vec<Decorator>,
     pub body: Script,
+    pub kind: FunctionKind,
+    /// Declared with `pub`, exported in the module's return table.
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionKind {
+    Plain,
+    /// Declared with `fn*`: Calls return an iterator over the yielded values.
+    Generator,
+    /// Declared with `async fn`: Calls return a task running the body.
+    Async,
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +46,7 @@ pub struct Extern {
 pub struct Lambda {
     pub arguments: Vec<Argument>,
     pub body: ScriptOrExpression,
+    pub kind: FunctionKind,
 }
 
 #[derive(Debug, Clone)]
@@ -59,10 +76,22 @@ pub enum MemberSegment {
     Computed(Expression),
     Identifier(Identifier),
     Dispatch(Identifier),
+    /// `a?[k]`
+    OptionalComputed(Expression),
+    /// `a?.b`
+    OptionalIdentifier(Identifier),
+}
+impl MemberSegment {
+    pub fn is_optional(&self) -> bool {
+        matches!(
+            self,
+            MemberSegment::OptionalComputed(_) | MemberSegment::OptionalIdentifier(_)
+        )
+    }
 }
-impl Into<CallExpressionVariant> for MemberSegment {
-    fn into(self) -> CallExpressionVariant {
-        CallExpressionVariant::Member(self)
+impl From<MemberSegment> for CallExpressionVariant {
+    fn from(val: MemberSegment) -> Self {
+        CallExpressionVariant::Member(val)
     }
 }
 
@@ -71,6 +100,12 @@ pub struct MemberExpression {
     pub head: Expression,
     pub tail: Vec<MemberSegment>,
 }
+impl MemberExpression {
+    /// True if any segment of the chain is nil-safe.
+    pub fn is_optional(&self) -> bool {
+        self.tail.iter().any(MemberSegment::is_optional)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum DestructureOrigin {
@@ -82,6 +117,10 @@ pub enum DestructureOrigin {
 #[derive(Debug, Clone)]
 pub enum DestructuringSegment {
     Identifier(Identifier),
+    /// A target with a value taken when the source's is `nil`.
+    Default((Identifier, Expression)),
+    /// Collects the remaining elements or fields, always the last segment.
+    Rest(Identifier),
     Destructuring((Identifier, Destructuring)),
 }
 
@@ -101,6 +140,10 @@ pub enum AssignmentTarget {
 pub struct Let {
     pub target: AssignmentTarget,
     pub value: Option<Expression>,
+    /// Diverging block run instead of binding, when the value is `nil`.
+    pub else_branch: Option<Script>,
+    /// Declared with `pub`, exported in the module's return table.
+    pub public: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -113,18 +156,38 @@ pub struct Assignment {
 #[derive(Debug, Clone)]
 pub struct Class {
     pub name: Identifier,
+    pub parent: Option<Identifier>,
     pub decorators: Vec<Decorator>,
     pub fields: Vec<ClassField>,
+    /// Declared with `pub`, exported in the module's return table.
+    pub public: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub name: Identifier,
+    pub variants: Vec<EnumVariant>,
+    /// Declared with `pub`, exported in the module's return table.
+    pub public: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: Identifier,
+    /// The payload field names, `None` for unit variants.
+    pub fields: Option<Vec<Identifier>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct CallSubExpression {
     pub callee: Option<MemberExpression>,
     pub arguments: Vec<Expression>,
+    /// `f?.(x)`, skips the call if the callee is nil.
+    pub optional: bool,
 }
-impl Into<CallExpressionVariant> for CallSubExpression {
-    fn into(self) -> CallExpressionVariant {
-        CallExpressionVariant::Call(self)
+impl From<CallSubExpression> for CallExpressionVariant {
+    fn from(val: CallSubExpression) -> Self {
+        CallExpressionVariant::Call(val)
     }
 }
 
@@ -164,6 +227,21 @@ pub struct CallExpression {
     pub head: CallSubExpression,
     pub tail: Vec<CallExpressionVariant>,
 }
+impl CallExpression {
+    /// True if any call or segment of the chain is nil-safe.
+    pub fn is_optional(&self) -> bool {
+        self.head.optional
+            || self
+                .head
+                .callee
+                .as_ref()
+                .is_some_and(MemberExpression::is_optional)
+            || self.tail.iter().any(|elem| match elem {
+                CallExpressionVariant::Call(c) => c.optional,
+                CallExpressionVariant::Member(m) => m.is_optional(),
+            })
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Return {
@@ -175,6 +253,7 @@ pub enum NumberVariant {
     Float(f64),
     Integer(i64),
     Hexadecimal(i64),
+    Octal(i64),
     Binary(i64),
     Character(i64),
 }
@@ -193,6 +272,22 @@ pub struct If {
     pub else_branch: Option<Script>,
 }
 
+/// A block that evaluates to its trailing expression, if any.
+#[derive(Debug, Clone)]
+pub struct ValueBlock {
+    pub body: Script,
+    pub value: Option<Expression>,
+}
+
+/// An `if` used as a value, `()` if no branch is taken.
+#[derive(Debug, Clone)]
+pub struct IfExpression {
+    pub condition: Expression,
+    pub body: ValueBlock,
+    pub branches: Vec<(Expression, ValueBlock)>,
+    pub else_branch: Option<ValueBlock>,
+}
+
 #[derive(Debug, Clone)]
 pub struct For {
     pub handler: AssignmentTarget,
@@ -212,6 +307,30 @@ pub struct While {
     pub body: Script,
 }
 
+/// `try { } catch (e) { } finally { }`, needs a catch or a finally block.
+#[derive(Debug, Clone)]
+pub struct Try {
+    pub body: Script,
+    pub catch: Option<Catch>,
+    pub finally: Option<Script>,
+}
+
+/// `catch (e, traceback) { }`, both names are optional.
+#[derive(Debug, Clone)]
+pub struct Catch {
+    /// Bound to the error value, as raised.
+    pub error: Option<Identifier>,
+    /// Bound to the stack traceback of where the error was raised.
+    pub traceback: Option<Identifier>,
+    pub body: Script,
+}
+
+/// Runs the expression when the enclosing block is left.
+#[derive(Debug, Clone)]
+pub struct Defer {
+    pub value: Expression,
+}
+
 #[derive(Debug, Clone)]
 pub struct Loop {
     pub body: Script,
@@ -239,12 +358,15 @@ pub struct UseStatement {
 pub enum Statement {
     MacroDecorator(Box<MacroDecorator>),
     If(If),
+    Try(Try),
+    Defer(Defer),
     Match(Match),
     For(For),
     Loop(Loop),
     While(While),
     Return(Return),
     Class(Class),
+    Enum(Enum),
     Extern(Extern),
     Function(Function),
     Assignment(Assignment),
@@ -257,6 +379,14 @@ pub enum Statement {
 pub enum ClassField {
     Method(Function),
     Let(Let),
+    /// A method marked with `static`, regardless of its first argument.
+    StaticMethod(Function),
+    /// A field marked with `static`, stored in the class itself.
+    StaticLet(Let),
+    /// `get name(self) { ... }`
+    Getter(Function),
+    /// `set name(self, value) { ... }`
+    Setter(Function),
 }
 
 #[derive(Debug, Clone)]
@@ -268,9 +398,9 @@ pub struct BinaryExpression {
     pub right: Expression,
     pub operator: Operator,
 }
-impl Into<Expression> for BinaryExpression {
-    fn into(self) -> Expression {
-        Expression::Binary(Box::new(self))
+impl From<BinaryExpression> for Expression {
+    fn from(val: BinaryExpression) -> Self {
+        Expression::Binary(Box::new(val))
     }
 }
 
@@ -279,9 +409,9 @@ pub struct UnaryExpression {
     pub expression: Expression,
     pub operator: Operator,
 }
-impl Into<Expression> for UnaryExpression {
-    fn into(self) -> Expression {
-        Expression::Unary(Box::new(self))
+impl From<UnaryExpression> for Expression {
+    fn from(val: UnaryExpression) -> Self {
+        Expression::Unary(Box::new(val))
     }
 }
 
@@ -290,6 +420,18 @@ pub struct SpreadExpression {
     pub expression: Expression,
 }
 
+/// Suspends the running generator, yielding the value.
+#[derive(Debug, Clone)]
+pub struct Yield {
+    pub value: Option<Expression>,
+}
+
+/// Suspends the running task until the awaited one finishes.
+#[derive(Debug, Clone)]
+pub struct Await {
+    pub value: Expression,
+}
+
 #[derive(Debug, Clone)]
 pub struct Vector {
     pub expressions: Vec<Expression>,
@@ -309,21 +451,40 @@ pub enum TableKeyExpression {
 
 #[derive(Debug, Clone)]
 pub struct StringLiteral {
-    pub value: String,
+    /// The bytes of the string, which can be any with `\x` escapes.
+    pub value: Vec<u8>,
     pub prefix: Option<Identifier>,
 }
 impl StringLiteral {
     pub fn expression_from_value(value: String) -> Expression {
         Expression::String(StringLiteral {
-            value,
+            value: value.into_bytes(),
             prefix: None,
         })
     }
+    /// The value as text, if it is valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.value).ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StringPart {
+    Literal(Vec<u8>),
+    Expression(Expression),
+}
+
+/// A string literal with embedded expressions, like `"Hello {name}!"`.
+#[derive(Debug, Clone)]
+pub struct InterpolatedString {
+    pub parts: Vec<StringPart>,
+    pub prefix: Option<Identifier>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Expression {
     Lambda(Box<Lambda>),
+    If(Box<IfExpression>),
     Reference(Box<MemberExpression>),
     Identifier(Identifier),
     Call(Box<CallExpression>),
@@ -335,8 +496,11 @@ pub enum Expression {
     Vector(Vector),
     Number(Number),
     String(StringLiteral),
+    Interpolated(Box<InterpolatedString>),
     Binary(Box<BinaryExpression>),
     Unary(Box<UnaryExpression>),
     Spread(Box<SpreadExpression>),
+    Yield(Box<Yield>),
+    Await(Box<Await>),
     Unit,
 }
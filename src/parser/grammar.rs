@@ -4,26 +4,41 @@ use super::helpers::generate_operator_function_name;
 peg::parser! {
     grammar saturnus_script() for str {
         pub rule script() -> Script
-            = ("#!" (!EOL() ANY())* EOL())? _ statements:statement() ** __ _
-            { Script { statements } }
+            = ("#!" (!EOL() ANY())* EOL())? _ statements:(p:position!() s:statement() { (p, s) }) ** __ _
+            {
+                let (positions, statements) = statements.into_iter().unzip();
+                Script { statements, positions }
+            }
 
         // Statements
         rule statement() -> Statement
             = e:macro_decorator() { Statement::MacroDecorator(Box::new(e)) }
             / e:use_statement() { Statement::UseStatement(e) }
+            / public_item()
             / e:class() { Statement::Class(e) }
+            / e:enum_decl() { Statement::Enum(e) }
             / e:func() { Statement::Function(e) }
             / e:extern_block() { Statement::Extern(e) }
             / e:for_each() { Statement::For(e) }
             / e:while_loop() { Statement::While(e) }
             / e:loop_loop() {  Statement::Loop(e) }
             / e:if_stmt() { Statement::If(e) }
+            / e:try_stmt() { Statement::Try(e) }
             / e:declare_var() { Statement::Let(e) }
             / e:assignment() { Statement::Assignment(e) }
             / e:return_stmt() { Statement::Return(e) }
+            / e:defer_stmt() { Statement::Defer(e) }
             / e:do_expression() { Statement::Expression(e) }
             / e:expression() _ EOS() { Statement::Expression(e) }
 
+        rule public_item() -> Statement
+            = decorators:decorator_list() PUB() __ e:class()
+            { Statement::Class(Class { decorators: [decorators, e.decorators].concat(), public: true, ..e }) }
+            / decorators:decorator_list() PUB() __ e:func()
+            { Statement::Function(Function { decorators: [decorators, e.decorators].concat(), public: true, ..e }) }
+            / PUB() __ e:enum_decl() { Statement::Enum(Enum { public: true, ..e }) }
+            / PUB() __ e:declare_var() { Statement::Let(Let { public: true, ..e }) }
+
         rule macro_decorator() -> MacroDecorator
             = "#[" _ macros:identifier_or_call() ++ (_ "," _) _ "]"
             _ target:statement()
@@ -35,27 +50,70 @@ peg::parser! {
             }
 
         rule identifier_or_call() -> IdentifierOrCall
-            = e:call_expression() { IdentifierOrCall::Call(e) }
+            = e:call_expression(true) { IdentifierOrCall::Call(e) }
             / e:identifier() { IdentifierOrCall::Identifier(e) }
 
         rule if_stmt() -> If
-            = "if" __ condition:expression() __ "{" body:script()
-              branches:("}" _ "else" __ "if" __ c:expression() __ "{" s:script() { (c, s) })*
+            = "if" __ condition:condition() _ "{" body:script()
+              branches:("}" _ "else" __ "if" __ c:condition() _ "{" s:script() { (c, s) })*
               else_branch:("}" _ "else" _ "{" e:script() {e})?
               "}"
             { If { condition, body, branches, else_branch } }
             / expected!("If statement")
 
+        rule try_stmt() -> Try
+            = "try" _ "{" body:script() "}"
+              catch:(_ "catch" _ names:("(" _ e:identifier() _ t:("," _ t:identifier() _ { t })? ")" _ { (e, t) })?
+                "{" body:script() "}"
+              {
+                let (error, traceback) = names.map_or((None, None), |(e, t)| (Some(e), t));
+                Catch { error, traceback, body }
+              })?
+              finally:(_ "finally" _ "{" s:script() "}" { s })?
+            {?
+                if catch.is_none() && finally.is_none() {
+                    return Err("Catch or finally block");
+                }
+                Ok(Try { body, catch, finally })
+            }
+            / expected!("Try statement")
+
+        rule diverging_block() -> Script
+            = "{" body:script() "}" {?
+                if diverges(&body) {
+                    Ok(body)
+                } else {
+                    Err("Diverging block (ending in return or error())")
+                }
+            }
+
+        rule if_expression() -> IfExpression
+            = "if" __ condition:condition() __ body:value_block()
+              branches:(_ "else" __ "if" __ c:condition() __ b:value_block() { (c, b) })*
+              else_branch:(_ "else" _ b:value_block() { b })?
+            { IfExpression { condition, body, branches, else_branch } }
+
+        rule yield_expression() -> Yield
+            = "yield" __ value:expression() { Yield { value: Some(value) } }
+            / "yield" !(ALPHA() / DIGIT()) { Yield { value: None } }
+
+        rule await_expression() -> Await
+            = AWAIT() __ value:expression() { Await { value } }
+
+        rule value_block() -> ValueBlock
+            = "{" body:script() value:(e:expression() _ { e })? "}"
+            { ValueBlock { body, value } }
+
         rule for_each() -> For
-            = "for" __ handler:assignment_target() __ "in" __ target:expression() _ "{"
+            = "for" __ handler:assignment_target() __ "in" __ target:condition() _ "{"
               body:script() "}"
             { For { handler, target, body } }
             / expected!("For loop")
 
         rule while_loop() -> While
-            = "while" __ c:expression() _ "{" body:script() "}"
+            = "while" __ c:condition() _ "{" body:script() "}"
             { While { condition: ExpressionOrLet::Expression(c), body } }
-            / "while" __ c:let_expression() _ "{" body:script() "}"
+            / "while" __ c:let_expression(false) _ "{" body:script() "}"
             { While { condition: ExpressionOrLet::Let(c), body } }
             / expected!("While loop")
 
@@ -65,30 +123,49 @@ peg::parser! {
             / expected!("Loop")
 
         rule func() -> Function
-            = decorators:decorator_list() FN() __ name:identifier() _ arguments:argument_list() _ body:func_body()
-            { Function { name, decorators, body, arguments } }
+            = decorators:decorator_list() kind:function_kind() __ name:identifier() _ arguments:argument_list() _ body:func_body()
+            { Function { name, decorators, body, arguments, kind, public: false } }
             / expected!("Function declaration")
 
+        rule function_kind() -> FunctionKind
+            = ASYNC() __ FN() { FunctionKind::Async }
+            / FN() _ "*" { FunctionKind::Generator }
+            / FN() { FunctionKind::Plain }
+
         rule func_body() -> Script
             = "{" body:script() "}" { body }
-            / "{" _ "}" { Script { statements: vec![] } }
-            / "=" _ value:expression() _ EOS() { Script { statements: vec![Statement::Return(Return { value })] } }
+            / "{" _ "}" { Script::default() }
+            / "=" _ p:position!() value:expression() _ EOS() { Script { statements: vec![Statement::Return(Return { value })], positions: vec![p] } }
 
         rule class() -> Class
             = decorators:decorator_list() CLASS()
-              __ name:identifier() _ "{"
+              __ name:identifier()
+              parent:(__ EXTENDS() __ p:identifier() {p})? _ "{"
               fields:(_ f:class_fields() _ {f})*
               _ "}"
-            { Class { name, fields, decorators } }
+            { Class { name, parent, fields, decorators, public: false } }
             / expected!("Class declaration")
 
+        rule enum_decl() -> Enum
+            = ENUM() __ name:identifier() _ "{" _
+              variants:enum_variant() ** (_ "," _) (_ ",")?
+              _ "}"
+            { Enum { name, variants, public: false } }
+            / expected!("Enum declaration")
+
+        rule enum_variant() -> EnumVariant
+            = name:identifier() fields:(_ "(" _ f:identifier() ** (_ "," _) _ ")" {f})?
+            { EnumVariant { name, fields } }
+
         rule declare_var() -> Let
-            = e:let_expression() _ EOS() { e }
+            = e:let_expression(true) _ "else" _ else_branch:diverging_block() (_ EOS())?
+            { Let { else_branch: Some(else_branch), ..e } }
+            / e:let_expression(true) _ EOS() { e }
             / expected!("Variable declaration")
 
         rule extern_block() -> Extern
             = "<extern" _ id:string_literal() ">" src:$(!"</extern>" ANY())* "</extern>"
-            { Extern { id: id.value, src: src.join("") } }
+            {? Ok(Extern { id: id.text().ok_or("UTF-8 extern name")?.into(), src: src.join("") }) }
 
         rule assignment() -> Assignment
             = target:member_expression() _ extra:extra_operator()? "=" _ value:expression() _ EOS()
@@ -101,10 +178,16 @@ peg::parser! {
         rule return_stmt() -> Return
             = "return" __ value:expression() _ EOS()
             { Return { value } }
+            / "return" _ EOS()
+            { Return { value: Expression::Unit } }
+
+        rule defer_stmt() -> Defer
+            = "defer" __ value:expression() _ EOS()
+            { Defer { value } }
 
         rule use_segment() -> String
             = value:identifier() { value.0 }
-            / value:string_literal() { value.value }
+            / value:string_literal() {? value.text().map(str::to_owned).ok_or("UTF-8 module name") }
 
         rule use_target() -> Vec<String>
             = value:use_segment() ++ (_ "." _) { value }
@@ -117,14 +200,21 @@ peg::parser! {
 
         // Expressions
         pub rule expression() -> Expression
-            = binary_expression()
+            = binary_expression(true)
+
+        // An expression followed by a block, whose braces can't be taken as a
+        // `callee { table }` call. Nested brackets allow them again.
+        rule condition() -> Expression
+            = binary_expression(false)
 
         rule member_expression() -> MemberExpression
             = head:primary()
             tail:(
                 _ "[" _ e:expression() _ "]" { MemberSegment::Computed(e) }
                 / _ "." _ i:identifier() { MemberSegment::Identifier(i) }
-                / _ "->" _ prop:identifier() { MemberSegment::Dispatch(prop).into() }
+                / _ "?[" _ e:expression() _ "]" { MemberSegment::OptionalComputed(e) }
+                / _ "?." _ i:identifier() { MemberSegment::OptionalIdentifier(i) }
+                / _ "->" _ prop:identifier() { MemberSegment::Dispatch(prop) }
             )*
             { MemberExpression { head, tail } }
 
@@ -132,7 +222,7 @@ peg::parser! {
             = target:identifier() "!" _ arguments:call_arguments()?
                 { MacroCallExpression { target, arguments } }
 
-        rule call_expression() -> CallExpression
+        rule call_expression(table_calls: bool) -> CallExpression
             = head:(
                   callee:member_expression() _ "in" _ arguments:do_literal()
                     { CallSubExpression { callee: Some(callee), arguments:vec![
@@ -140,25 +230,40 @@ peg::parser! {
                             arguments: vec![Argument {
                                 name: Identifier("it".into()),
                                 spread: false,
-                                decorators: vec![]
+                                decorators: vec![],
+                                default: None,
+                                pattern: None,
                             }, Argument {
                                 name: Identifier("rest".into()),
                                 spread: true,
-                                decorators: vec![]
+                                decorators: vec![],
+                                default: None,
+                                pattern: None,
                             }],
-                            body: ScriptOrExpression::Script(arguments.body)
+                            body: ScriptOrExpression::Script(arguments.body),
+                            kind: FunctionKind::Plain,
                         }))
-                    ] }.into() }
+                    ], optional: false } }
                 / callee:member_expression() _ arguments:call_arguments()
-                    { CallSubExpression { callee: Some(callee), arguments }.into() }
-                / callee:member_expression() _ arg:table_expression()
-                    { CallSubExpression { callee: Some(callee), arguments: vec![arg] } }
+                    { CallSubExpression { callee: Some(callee), arguments, optional: false } }
+                / callee:member_expression() _ "?." _ arguments:call_arguments()
+                    { CallSubExpression { callee: Some(callee), arguments, optional: true } }
+                / callee:member_expression() _ arg:table_expression() {?
+                    if table_calls {
+                        Ok(CallSubExpression { callee: Some(callee), arguments: vec![arg], optional: false })
+                    } else {
+                        Err("Table call")
+                    }
+                }
             )
             tail:(
                   _ "[" _ prop:expression() _ "]" { MemberSegment::Computed(prop).into() }
                 / _ "." _ prop:identifier() { MemberSegment::Identifier(prop).into() }
                 / _ "->" _ prop:identifier() { MemberSegment::Dispatch(prop).into() }
-                / _ arguments:call_arguments() { CallSubExpression { callee: None, arguments }.into() }
+                / _ "?[" _ prop:expression() _ "]" { MemberSegment::OptionalComputed(prop).into() }
+                / _ "?." _ arguments:call_arguments() { CallSubExpression { callee: None, arguments, optional: true }.into() }
+                / _ "?." _ prop:identifier() { MemberSegment::OptionalIdentifier(prop).into() }
+                / _ arguments:call_arguments() { CallSubExpression { callee: None, arguments, optional: false }.into() }
             )*
             { CallExpression { head, tail } }
 
@@ -178,10 +283,13 @@ peg::parser! {
             { args.unwrap_or(vec![]) }
 
         rule call_argument_list() -> Vec<Expression>
-            = args:expression() ** (_ "," _)
-            { args }
+            = args:call_argument() ** (_ "," _) {? lower_named_arguments(args) }
+
+        rule call_argument() -> (Option<Identifier>, Expression)
+            = name:identifier() _ ":" _ value:expression() { (Some(name), value) }
+            / value:expression() { (None, value) }
 
-        rule binary_expression() -> Expression = precedence! {
+        rule binary_expression(table_calls: bool) -> Expression = precedence! {
             "..." _ expression:@ { Expression::Spread(Box::new(SpreadExpression { expression })) }
             --
             value:$("-") _ expression:@ { UnaryExpression { expression, operator: Operator(value.into()) }.into() }
@@ -235,12 +343,15 @@ peg::parser! {
                 BinaryExpression { left, right, operator: Operator(value.into()) }.into()
             }
             --
-            e:atom() { e }
+            e:atom(table_calls) { e }
         }
 
-        rule atom() -> Expression
-            = e:macro_call_expression() { Expression::MacroCall(Box::new(e)) }
-            / e:call_expression() { Expression::Call(Box::new(e)) }
+        rule atom(table_calls: bool) -> Expression
+            = e:if_expression() { Expression::If(Box::new(e)) }
+            / e:yield_expression() { Expression::Yield(Box::new(e)) }
+            / e:await_expression() { Expression::Await(Box::new(e)) }
+            / e:macro_call_expression() { Expression::MacroCall(Box::new(e)) }
+            / e:call_expression(table_calls) { Expression::Call(Box::new(e)) }
             / lambda_expression()
             / string_expression()
             / number_expression()
@@ -253,7 +364,15 @@ peg::parser! {
             / enclosed_expression()
 
         // Literal-to-expression
-        rule string_expression() -> Expression = e:string_literal() { Expression::String(e) }
+        rule string_expression() -> Expression
+            = value:raw_string() { Expression::String(StringLiteral { value, prefix: None }) }
+            / prefix:identifier()? parts:string_parts()
+            {
+                match plain_string(&parts) {
+                    Some(value) => Expression::String(StringLiteral { value, prefix }),
+                    None => Expression::Interpolated(Box::new(InterpolatedString { parts, prefix })),
+                }
+            }
         rule number_expression() -> Expression = e:number_literal() { Expression::Number(e) }
         rule lambda_expression() -> Expression = e:lambda_literal() { Expression::Lambda(Box::new(e)) }
         rule vector_expression() -> Expression = e:vector_literal() { Expression::Vector(e) }
@@ -266,37 +385,111 @@ peg::parser! {
 
         rule lambda_literal() -> Lambda
             = name:identifier() _ "=>" _ "{" body:script() "}"
-            { Lambda { arguments: vec![Argument { name, decorators: vec![], spread: false }], body: ScriptOrExpression::Script(body) } }
+            { Lambda { arguments: vec![Argument { name, decorators: vec![], spread: false, default: None, pattern: None }], body: ScriptOrExpression::Script(body), kind: FunctionKind::Plain } }
             / name:identifier() _ "=>" _ body:expression()
-            { Lambda { arguments: vec![Argument { name, decorators: vec![], spread: false }], body: ScriptOrExpression::Expression(body) } }
+            { Lambda { arguments: vec![Argument { name, decorators: vec![], spread: false, default: None, pattern: None }], body: ScriptOrExpression::Expression(body), kind: FunctionKind::Plain } }
             / arguments:argument_list() _ "=>" _ "{" body:script() "}"
-            { Lambda { arguments, body: ScriptOrExpression::Script(body) } }
+            { Lambda { arguments, body: ScriptOrExpression::Script(body), kind: FunctionKind::Plain } }
             / arguments:argument_list() _ "=>" _ expr:expression()
-            { Lambda { arguments, body: ScriptOrExpression::Expression(expr) } }
+            { Lambda { arguments, body: ScriptOrExpression::Expression(expr), kind: FunctionKind::Plain } }
             / arguments:argument_list() _ "=>" _ "{" _ "}"
-            { Lambda { arguments, body: ScriptOrExpression::Script(Script { statements: vec![] }) } }
+            { Lambda { arguments, body: ScriptOrExpression::Script(Script::default()), kind: FunctionKind::Plain } }
 
         // Literals
         rule number_literal() -> Number
             = value:number_literal_value() postfix:identifier()?
                 { Number { value, postfix } }
         rule number_literal_value() -> NumberVariant
-            = value:$(DIGIT()+ "." DIGIT()+) { NumberVariant::Float(value.parse().unwrap()) }
-            / "0x" value:$((DIGIT() / HEX())+) { NumberVariant::Hexadecimal(i64::from_str_radix(value, 16).unwrap()) }
-            / "0b" value:$(['1'|'0']+) { NumberVariant::Binary(i64::from_str_radix(value, 2).unwrap()) }
-            / value:$(DIGIT()+) { NumberVariant::Integer(value.parse().unwrap()) }
-            / "'" value:$(!"'" ANY()) "'" { NumberVariant::Character(value.chars().nth(0).unwrap() as i64) }
+            // A fraction needs digits or an exponent, so `0x10..0x20` is a range.
+            = "0x" int:$(hex_digits()?) frac:("." f:$(hex_digits()) { f } / "." &['p'|'P'] { "" })?
+                exp:(['p'|'P'] e:$(['+'|'-']? dec_digits()) { e })? {?
+                match (frac, exp) {
+                    (None, None) if int.is_empty() => Err("Hexadecimal digits"),
+                    (None, None) => parse_radix(int, 16).map(NumberVariant::Hexadecimal),
+                    (frac, exp) => parse_hex_float(int, frac.unwrap_or(""), exp.unwrap_or("0"))
+                        .map(NumberVariant::Float),
+                }
+            }
+            / "0o" value:$(oct_digits()) {? parse_radix(value, 8).map(NumberVariant::Octal) }
+            / "0b" value:$(bin_digits()) {? parse_radix(value, 2).map(NumberVariant::Binary) }
+            / value:$(dec_digits()? "." dec_digits() exponent()? / dec_digits() exponent()) {?
+                parse_float(value).map(NumberVariant::Float)
+            }
+            // Overflowing literals must not be taken as an integer with a postfix.
+            / !("0x" (DIGIT() / HEX()) / "0o" ['0'..='7'] / "0b" ['0'|'1'])
+                value:$(dec_digits()) !exponent() !("." DIGIT()) {?
+                value
+                    .replace('_', "")
+                    .parse()
+                    .map(NumberVariant::Integer)
+                    .or(Err("Integer literal within 64 bits"))
+            }
+            / "'" value:$(!"'" ANY()) "'" { NumberVariant::Character(value.chars().next().unwrap() as i64) }
             / expected!("Number literal")
 
+        // Underscores may separate digits, but never lead nor trail them.
+        rule dec_digits() = DIGIT() ("_"* DIGIT())*
+        rule hex_digits() = (DIGIT() / HEX()) ("_"* (DIGIT() / HEX()))*
+        rule oct_digits() = ['0'..='7'] ("_"* ['0'..='7'])*
+        rule bin_digits() = ['0'|'1'] ("_"* ['0'|'1'])*
+        rule exponent() = ['e'|'E'] ['+'|'-']? dec_digits()
+
         rule string_literal() -> StringLiteral
-            = prefix:identifier()? value:string_literal_value()
+            = value:raw_string() { StringLiteral { value, prefix: None } }
+            / prefix:identifier()? value:string_literal_value()
                 { StringLiteral { value, prefix } }
-        rule string_literal_value() -> String
-            = "\"" value:$(
-                ( "\\\"" / (!"\"" ANY()) )*
-            ) "\"" { value.into() }
+        rule string_literal_value() -> Vec<u8>
+            = parts:string_parts() {?
+                plain_string(&parts).ok_or("String literal without interpolation")
+            }
             / expected!("String literal")
 
+        // Raw strings: No escapes, nor interpolation. The hash allows quotes
+        // inside, so `r` can't be used as a string prefix.
+        rule raw_string() -> Vec<u8>
+            = "r#\"" value:$((!"\"#" ANY())*) "\"#" { value.into() }
+            / "r\"" value:$((!"\"" ANY())*) "\"" { value.into() }
+
+        rule string_parts() -> Vec<StringPart>
+            = "\"\"\"" ("\r\n" / "\n")? parts:string_part(<"\"\"\"">)* "\"\"\""
+            { merge_literals(parts) }
+            / "\"" parts:string_part(<"\"">)* "\""
+            { merge_literals(parts) }
+
+        // Braces that don't enclose an expression are kept as they are.
+        rule string_part(end: rule<()>) -> StringPart
+            = "{{" { StringPart::Literal("{".into()) }
+            / "}}" { StringPart::Literal("}".into()) }
+            / "{" _ e:expression() _ "}" { StringPart::Expression(e) }
+            / value:escape_sequence() { StringPart::Literal(value) }
+            / !end() !['\\'] value:$(ANY()) { StringPart::Literal(value.into()) }
+
+        rule escape_sequence() -> Vec<u8>
+            = "\\x" value:$((DIGIT() / HEX())*<2>) {?
+                u8::from_str_radix(value, 16)
+                    .map(|byte| vec![byte])
+                    .or(Err("Byte value (\\x00 to \\xFF)"))
+            }
+            / c:escaped_char() { c.to_string().into_bytes() }
+
+        rule escaped_char() -> char
+            = "\\n" { '\n' }
+            / "\\r" { '\r' }
+            / "\\t" { '\t' }
+            / "\\0" { '\0' }
+            / "\\\\" { '\\' }
+            / "\\\"" { '"' }
+            / "\\'" { '\'' }
+            / "\\{" { '{' }
+            / "\\}" { '}' }
+            / "\\u{" value:$((DIGIT() / HEX())*<1,6>) "}" {?
+                u32::from_str_radix(value, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or("Unicode scalar value")
+            }
+            / "\\" {? Err("Escape sequence") }
+
         rule vector_literal() -> Vector
             = "[" _ expressions:comma_expr() _ "]"
             { Vector { expressions } }
@@ -318,9 +511,9 @@ peg::parser! {
             / expected!("Do block")
 
         // Auxiliaries and sub-expressions
-        rule let_expression() -> Let
-            = "let" __ target:assignment_target() value:(_ "=" _ e:expression(){e})?
-            { Let { target, value } }
+        rule let_expression(table_calls: bool) -> Let
+            = "let" __ target:assignment_target() value:(_ "=" _ e:binary_expression(table_calls){e})?
+            { Let { target, value, else_branch: None, public: false } }
 
         rule assignment_target() -> AssignmentTarget
             = e:identifier() { AssignmentTarget::Identifier(e) }
@@ -328,44 +521,81 @@ peg::parser! {
 
         rule destructure_expression() -> Destructuring
             = "{" _ targets:destructure_body_table() _ "}" { Destructuring { targets, origin: DestructureOrigin::Table } }
-            / "(" _ targets:destructure_body_linear() _ ")" { Destructuring { targets, origin: DestructureOrigin::Tuple } }
+            / "(" _ targets:destructure_body_linear() _ ")" {?
+                if targets.iter().any(|t| matches!(t, DestructuringSegment::Rest(_))) {
+                    return Err("Tuple element (tuples have no rest)");
+                }
+                Ok(Destructuring { targets, origin: DestructureOrigin::Tuple })
+            }
             / "[" _ targets:destructure_body_linear() _ "]" { Destructuring { targets, origin: DestructureOrigin::Array } }
 
         rule destructure_body_linear() -> Vec<DestructuringSegment>
             = target:(
-                dt:destructure_expression() { DestructuringSegment::Destructuring((Identifier("".into()), dt)) }
-                / i:identifier() { DestructuringSegment::Identifier(i) }
-            ) ** (_ "," _) { target }
+                destructure_rest()
+                / dt:destructure_expression() { DestructuringSegment::Destructuring((Identifier("".into()), dt)) }
+                / destructure_target()
+            ) ** (_ "," _) {? rest_last(target) }
 
         rule destructure_body_table() -> Vec<DestructuringSegment>
-            = target:destructure_fragment() ** (_ "," _) { target }
+            = target:destructure_fragment() ** (_ "," _) {? rest_last(target) }
 
         rule destructure_fragment() -> DestructuringSegment
-            = i:identifier() _ ":" _ t:destructure_expression()
+            = destructure_rest()
+            / i:identifier() _ ":" _ t:destructure_expression()
                 { DestructuringSegment::Destructuring((i, t)) }
-            / i:identifier() { DestructuringSegment::Identifier(i) }
+            / destructure_target()
+
+        rule destructure_target() -> DestructuringSegment
+            = i:identifier() default:default_value()? {
+                match default {
+                    Some(default) => DestructuringSegment::Default((i, default)),
+                    None => DestructuringSegment::Identifier(i),
+                }
+            }
+
+        rule destructure_rest() -> DestructuringSegment
+            = "..." _ i:identifier() { DestructuringSegment::Rest(i) }
 
         rule class_fields() -> ClassField
-            = e:declare_var() { ClassField::Let(e) }
+            = STATIC() __ e:declare_var() { ClassField::StaticLet(e) }
+            / e:declare_var() { ClassField::Let(e) }
+            / GET() __ e:accessor() { ClassField::Getter(e) }
+            / SET() __ e:accessor() { ClassField::Setter(e) }
+            / decorators:decorator_list() STATIC() __ e:func()
+            { ClassField::StaticMethod(Function { decorators, ..e }) }
             / e:func() { ClassField::Method(e) }
 
+        rule accessor() -> Function
+            = name:identifier() _ arguments:argument_list() _ body:func_body()
+            { Function { name, decorators: vec![], body, arguments, kind: FunctionKind::Plain, public: false } }
+
         rule argument_list() -> Vec<Argument>
             = "(" _ args:argument() ** (_ "," _) _ ")" { args }
 
         rule argument() -> Argument
-            = decorators:decorator_list() spread:(_ "*" _)? name:identifier()
-            { Argument { name, decorators, spread: spread.is_some() } }
+            = decorators:decorator_list() _ "*" _ name:identifier()
+            { Argument { name, decorators, spread: true, default: None, pattern: None } }
+            / decorators:decorator_list() pattern:destructure_expression() default:default_value()?
+            {
+                let name = Identifier("".into());
+                Argument { name, decorators, spread: false, default, pattern: Some(pattern) }
+            }
+            / decorators:decorator_list() name:identifier() default:default_value()?
+            { Argument { name, decorators, spread: false, default, pattern: None } }
+
+        rule default_value() -> Expression
+            = _ "=" !['=' | '>'] _ e:expression() { e }
 
         rule decorator_list() -> Vec<Decorator>
             = e:decorator() ++ _ _ { e }
             / { vec![] }
 
         rule decorator() -> Decorator
-            = "@" _ target:call_expression() { Decorator { target } }
+            = "@" _ target:call_expression(true) { Decorator { target } }
             / expected!("Decorator")
 
         rule identifier() -> Identifier
-            = "`" value:$(ANY_OPERATOR()) "`"
+            = "`" value:$(ANY_OPERATOR() / "[]" / "()") "`"
             { Identifier(generate_operator_function_name(value.to_owned()))
             }
             / value:$(IDENT() !"let") { Identifier(value.into()) }
@@ -401,9 +631,17 @@ peg::parser! {
         rule LET() = "let"
         rule MUT() = "mut"
         rule CLASS() = "class"
+        rule ENUM() = "enum"
+        rule EXTENDS() = "extends"
+        rule STATIC() = "static"
+        rule PUB() = "pub"
+        rule GET() = "get"
+        rule SET() = "set"
         rule END() = "end"
         rule USE() = "use"
         rule FN() = "fn"
+        rule ASYNC() = "async"
+        rule AWAIT() = "await"
         rule EXTERN() = "extern"
         rule NATIVE() = "native" // Deprecated!
         rule MACRO() = "macro"
@@ -446,11 +684,118 @@ peg::parser! {
         }
 }
 
+/// Joins the parts of a string literal, if none of them is interpolated.
+fn plain_string(parts: &[StringPart]) -> Option<Vec<u8>> {
+    parts
+        .iter()
+        .map(|part| match part {
+            StringPart::Literal(value) => Some(value.as_slice()),
+            StringPart::Expression(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|values| values.concat())
+}
+
+/// Joins adjacent literal parts of a string.
+fn merge_literals(parts: Vec<StringPart>) -> Vec<StringPart> {
+    parts.into_iter().fold(vec![], |mut parts, part| {
+        match (parts.last_mut(), part) {
+            (Some(StringPart::Literal(prev)), StringPart::Literal(next)) => prev.extend(next),
+            (_, part) => parts.push(part),
+        }
+        parts
+    })
+}
+
+/// Parses integer digits in the given radix. Like in Lua, non-decimal literals
+/// may use all 64 bits, wrapping around to negative numbers.
+fn parse_radix(digits: &str, radix: u32) -> Result<i64, &'static str> {
+    u64::from_str_radix(&digits.replace('_', ""), radix)
+        .map(|value| value as i64)
+        .or(Err("Integer literal within 64 bits"))
+}
+
+fn parse_float(value: &str) -> Result<f64, &'static str> {
+    value
+        .replace('_', "")
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or("Float literal within 64 bits")
+}
+
+/// Parses a hexadecimal float, like `0x1.8p3` (`1.5 * 2^3`).
+fn parse_hex_float(int: &str, frac: &str, exp: &str) -> Result<f64, &'static str> {
+    let int = int.replace('_', "");
+    let frac = frac.replace('_', "");
+    if int.is_empty() && frac.is_empty() {
+        return Err("Hexadecimal digits");
+    }
+    let mantissa = int.chars().chain(frac.chars()).fold(0_f64, |acc, digit| {
+        acc * 16.0 + digit.to_digit(16).unwrap() as f64
+    });
+    let exp = exp
+        .replace('_', "")
+        .parse::<i32>()
+        .or(Err("Float literal within 64 bits"))?;
+    Some(mantissa * 2_f64.powi(exp - 4 * frac.len() as i32))
+        .filter(|value| value.is_finite())
+        .ok_or("Float literal within 64 bits")
+}
+
+/// Lowers named call arguments into a trailing options table, so
+/// `f(a, b: 1, c: 2)` becomes `f(a, { b: 1, c: 2 })`.
+fn lower_named_arguments(
+    args: Vec<(Option<Identifier>, Expression)>,
+) -> Result<Vec<Expression>, &'static str> {
+    let mut positional = vec![];
+    let mut named = vec![];
+    for (name, value) in args {
+        match name {
+            Some(name) => named.push((TableKeyExpression::Identifier(name), Some(value))),
+            None if named.is_empty() => positional.push(value),
+            None => return Err("Named argument (positional ones go first)"),
+        }
+    }
+    if !named.is_empty() {
+        positional.push(Expression::Table(Table { key_values: named }));
+    }
+    Ok(positional)
+}
+
+/// Checks that a rest element, if any, is the last of a destructuring.
+fn rest_last(
+    targets: Vec<DestructuringSegment>,
+) -> Result<Vec<DestructuringSegment>, &'static str> {
+    let rest = targets
+        .iter()
+        .position(|t| matches!(t, DestructuringSegment::Rest(_)));
+    match rest {
+        Some(i) if i + 1 < targets.len() => Err("Rest element at the end of the destructuring"),
+        _ => Ok(targets),
+    }
+}
+
+/// Whether the block never completes normally: It returns or raises an error.
+fn diverges(script: &Script) -> bool {
+    match script.statements.last() {
+        Some(Statement::Return(_)) => true,
+        Some(Statement::Expression(Expression::Call(call))) => matches!(
+            &call.head.callee,
+            Some(MemberExpression { head: Expression::Identifier(Identifier(name)), tail })
+                if name == "error" && tail.is_empty() && call.tail.is_empty()
+        ),
+        _ => false,
+    }
+}
+
 pub type ParseResult = Result<Script, peg::error::ParseError<peg::str::LineCol>>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Script {
     pub statements: Vec<Statement>,
+    /// Where each statement starts, as a byte offset in the source.
+    pub positions: Vec<usize>,
 }
 impl Script {
     pub fn parse<I>(input: I) -> ParseResult
@@ -18,6 +18,10 @@ pub fn translate_operator_symbol(operator: &u8) -> String {
         b'$' => "dollar",
         b'^' => "power",
         b':' => "colon",
+        b'[' => "open_bracket",
+        b']' => "close_bracket",
+        b'(' => "open_paren",
+        b')' => "close_paren",
         _ => panic!(
             "Error! Unexpected operator {} to be translated as a function!",
             operator
@@ -37,3 +41,50 @@ pub fn generate_operator_function_name(operator_chain: String) -> String {
             .join("_")
     )
 }
+
+/// Operators that classes can overload, and the Lua metamethod each one maps
+/// to. Unary operators take only `self`.
+const METAMETHODS: [(&str, usize, &str); 18] = [
+    ("+", 2, "__add"),
+    ("-", 2, "__sub"),
+    ("-", 1, "__unm"),
+    ("*", 2, "__mul"),
+    ("/", 2, "__div"),
+    ("%", 2, "__mod"),
+    ("**", 2, "__pow"),
+    ("++", 2, "__concat"),
+    ("==", 2, "__eq"),
+    ("<", 2, "__lt"),
+    ("<=", 2, "__le"),
+    ("&", 2, "__band"),
+    ("|", 2, "__bor"),
+    ("<<", 2, "__shl"),
+    (">>", 2, "__shr"),
+    ("#?", 1, "__len"),
+    ("#", 1, "__len"),
+    ("()", 0, "__call"),
+];
+
+/// The metamethod implemented by an operator method with the given (already
+/// translated) name and amount of arguments, if any. Call operators take any
+/// amount of arguments.
+pub fn operator_metamethod(name: &str, arity: usize) -> Option<&'static str> {
+    METAMETHODS
+        .iter()
+        .find(|(op, args, _)| {
+            (*args == 0 || *args == arity)
+                && generate_operator_function_name(op.to_string()) == name
+        })
+        .map(|(_, _, meta)| *meta)
+}
+
+/// Binary operators translated to native Lua ones, any other operator is a
+/// call to its `__saturnus_operator_*` function.
+const NATIVE_OPERATORS: [&str; 24] = [
+    "+", "-", "*", "/", "%", "**", ">", ">=", "<", "<=", "==", "not", "and", "or", "&", "|", "<<",
+    "<<<", ">>", ">>>", "++", "<>", "??", "?:",
+];
+
+pub fn is_native_operator(operator: &str) -> bool {
+    NATIVE_OPERATORS.contains(&operator)
+}
@@ -1,21 +1,320 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use crate::{
     code::{
         ast_visitor::{VisitError, Visitor},
         builder::Builder,
     },
-    parser::Script,
+    engine::Sandbox,
+    parser::{
+        ast::{AssignmentTarget, Expression, Let, Statement, TableKeyExpression},
+        Script,
+    },
 };
 
+pub const STD_SRC: &str = include_str!("assets/std.saturn");
+
+/// Compiles the bundled standard library with the given compiler, returning
+/// the generated code and its checksum.
+pub fn precompile_std(compiler: &dyn Visitor) -> Result<(String, md5::Digest), RuntimeError> {
+    let std_src = Script::parse(STD_SRC).map_err(RuntimeError::ParseError)?;
+    let std_src = compiler
+        .visit_script(Builder::new("  "), &std_src)
+        .map_err(RuntimeError::CompilationError)?
+        .collect();
+    let crc = md5::compute(std_src.as_bytes());
+    Ok((std_src, crc))
+}
+
+/// The parts of the standard library that a program imports.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StdUsage {
+    /// The whole library, as with `use std;`.
+    All,
+    /// Only these exports, nested ones (Like operators) by their own name.
+    Only(HashSet<String>),
+}
+impl Default for StdUsage {
+    fn default() -> Self {
+        StdUsage::Only(HashSet::new())
+    }
+}
+impl StdUsage {
+    pub fn import<I>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        if let StdUsage::Only(used) = self {
+            used.extend(names);
+        }
+    }
+    pub fn merge(&mut self, other: &StdUsage) {
+        match other {
+            StdUsage::All => *self = StdUsage::All,
+            StdUsage::Only(names) => self.import(names.iter().cloned()),
+        }
+    }
+}
+
+/// Like `precompile_std`, but leaves out the definitions that the used
+/// exports don't reach.
+pub fn precompile_std_for(
+    compiler: &dyn Visitor,
+    usage: &StdUsage,
+) -> Result<(String, md5::Digest), RuntimeError> {
+    let StdUsage::Only(used) = usage else {
+        return precompile_std(compiler);
+    };
+    let std_src = Script::parse(STD_SRC).map_err(RuntimeError::ParseError)?;
+    let std_src = shake_std(compiler, std_src, used).map_err(RuntimeError::CompilationError)?;
+    let std_src = compiler
+        .visit_script(Builder::new("  "), &std_src)
+        .map_err(RuntimeError::CompilationError)?
+        .collect();
+    let crc = md5::compute(std_src.as_bytes());
+    Ok((std_src, crc))
+}
+
+/// The names declared by a top-level statement.
+fn declared_names(stmt: &Statement) -> Vec<String> {
+    match stmt {
+        Statement::Function(e) => vec![e.name.0.clone()],
+        Statement::Class(e) => vec![e.name.0.clone()],
+        Statement::Enum(e) => vec![e.name.0.clone()],
+        Statement::Let(Let {
+            target: AssignmentTarget::Identifier(id),
+            ..
+        }) => vec![id.0.clone()],
+        _ => vec![],
+    }
+}
+
+/// Every identifier-like token of the code generated for the statement.
+/// May over-approximate (Field names, strings), which only keeps more code.
+fn referenced_names(
+    compiler: &dyn Visitor,
+    stmt: &Statement,
+) -> Result<HashSet<String>, VisitError> {
+    let code = compiler.visit_statement(Builder::new(""), stmt)?.collect();
+    Ok(code
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|token| !token.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Drops the exports that aren't used from the std return table, then the
+/// top-level definitions that the remaining exports don't depend on.
+fn shake_std(
+    compiler: &dyn Visitor,
+    mut script: Script,
+    used: &HashSet<String>,
+) -> Result<Script, VisitError> {
+    let Some(Statement::Return(exports)) = script.statements.last_mut() else {
+        return Ok(script);
+    };
+    if let Expression::Table(table) = &mut exports.value {
+        table.key_values.retain_mut(|(key, value)| {
+            let key = match key {
+                TableKeyExpression::Identifier(id) | TableKeyExpression::Implicit(id) => &id.0,
+                TableKeyExpression::Expression(_) => return true,
+            };
+            if used.contains(key) {
+                return true;
+            }
+            match value {
+                Some(Expression::Table(nested)) => {
+                    nested.key_values.retain(|(key, _)| match key {
+                        TableKeyExpression::Identifier(id) | TableKeyExpression::Implicit(id) => {
+                            used.contains(&id.0)
+                        }
+                        TableKeyExpression::Expression(_) => true,
+                    });
+                    !nested.key_values.is_empty()
+                }
+                _ => false,
+            }
+        });
+    }
+    let declared = script
+        .statements
+        .iter()
+        .enumerate()
+        .flat_map(|(i, stmt)| declared_names(stmt).into_iter().map(move |name| (name, i)))
+        .collect::<HashMap<String, usize>>();
+    // Statements that declare nothing run for their side effects, always kept.
+    let mut pending = script
+        .statements
+        .iter()
+        .enumerate()
+        .filter(|(_, stmt)| declared_names(stmt).is_empty())
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+    let mut kept = pending.iter().copied().collect::<HashSet<usize>>();
+    while let Some(i) = pending.pop() {
+        for name in referenced_names(compiler, &script.statements[i])? {
+            if let Some(&j) = declared.get(&name) {
+                if kept.insert(j) {
+                    pending.push(j);
+                }
+            }
+        }
+    }
+    script.statements = script
+        .statements
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| kept.contains(i))
+        .map(|(_, stmt)| stmt)
+        .collect();
+    Ok(script)
+}
+
 #[derive(Debug)]
 pub enum RuntimeError {
     EvaluationError(rlua::Error),
     ParseError(peg::error::ParseError<peg::str::LineCol>),
     CompilationError(VisitError),
+    /// A sandboxed evaluation ran more VM instructions than allowed.
+    InstructionLimitExceeded(u64),
+    /// A sandboxed evaluation grew the Lua heap past the given bytes.
+    MemoryLimitExceeded(usize),
+    /// A sandboxed evaluation took longer than allowed.
+    TimeoutExceeded(Duration),
+}
+
+/// A Lua value copied out of the virtual machine, so it can outlive the
+/// evaluation that produced it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    /// Key-value pairs, in the order that Lua's `next` yields them.
+    Table(Vec<(Value, Value)>),
+    /// Functions, threads, userdata and self-referencing tables, which can't
+    /// be copied. Holds the Lua type name.
+    Opaque(&'static str),
+}
+
+impl Value {
+    /// Copies the given Lua value, tables are copied deeply.
+    pub fn from_lua<'lua>(
+        ctx: rlua::Context<'lua>,
+        value: rlua::Value<'lua>,
+    ) -> rlua::Result<Value> {
+        // Tables currently being copied, to break reference cycles.
+        let visiting = ctx.create_table()?;
+        Self::copy(value, &visiting)
+    }
+
+    fn copy<'lua>(value: rlua::Value<'lua>, visiting: &rlua::Table<'lua>) -> rlua::Result<Value> {
+        Ok(match value {
+            rlua::Value::Nil => Value::Nil,
+            rlua::Value::Boolean(value) => Value::Boolean(value),
+            rlua::Value::Integer(value) => Value::Integer(value),
+            rlua::Value::Number(value) => Value::Number(value),
+            rlua::Value::String(value) => {
+                Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned())
+            }
+            rlua::Value::Table(table) => {
+                if visiting.raw_get::<_, bool>(table.clone())? {
+                    return Ok(Value::Opaque("table"));
+                }
+                visiting.raw_set(table.clone(), true)?;
+                let entries = table
+                    .clone()
+                    .pairs::<rlua::Value, rlua::Value>()
+                    .map(|pair| {
+                        let (key, value) = pair?;
+                        Ok((Self::copy(key, visiting)?, Self::copy(value, visiting)?))
+                    })
+                    .collect::<rlua::Result<Vec<_>>>()?;
+                visiting.raw_set(table, rlua::Nil)?;
+                Value::Table(entries)
+            }
+            other => Value::Opaque(other.type_name()),
+        })
+    }
+
+    /// Looks up a string key, if this value is a table.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Table(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Value::String(k) if k == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
 }
 
 pub struct EvaluationOutput {
+    /// Everything the script wrote with `print` and `io.write`.
     pub stdout: String,
-    pub vars: (),
+    /// The value returned by the script, `Nil` if none.
+    pub value: Value,
+    /// The globals defined by the script, or by any previous evaluation in
+    /// the same host. Lua's own globals are left out.
+    pub vars: HashMap<String, Value>,
+}
+
+fn append(buffer: &Mutex<String>, args: &[rlua::String]) {
+    let mut buffer = buffer.lock().unwrap();
+    for arg in args {
+        buffer.push_str(&String::from_utf8_lossy(arg.as_bytes()));
+    }
+}
+
+/// Replaces `print`, `io.write` and `io.stdout` with stand-ins that append
+/// to the given buffer instead of the process' standard output.
+fn redirect_output(ctx: rlua::Context, stdout: &Arc<Mutex<String>>) -> rlua::Result<()> {
+    let globals = ctx.globals();
+    let buffer = stdout.clone();
+    let print = ctx.create_function(move |ctx, args: rlua::MultiValue| {
+        let tostring: rlua::Function = ctx.globals().get("tostring")?;
+        let line = args
+            .into_iter()
+            .map(|arg| {
+                let text = tostring.call::<_, rlua::String>(arg)?;
+                Ok(String::from_utf8_lossy(text.as_bytes()).into_owned())
+            })
+            .collect::<rlua::Result<Vec<_>>>()?
+            .join("\t");
+        let mut buffer = buffer.lock().unwrap();
+        buffer.push_str(&line);
+        buffer.push('\n');
+        Ok(())
+    })?;
+    globals.set("print", print)?;
+    if let Ok(io) = globals.get::<_, rlua::Table>("io") {
+        // Stands in for `io.stdout`, so chained calls like
+        // io.write("a"):write("b") land in the buffer too.
+        let proxy = ctx.create_table()?;
+        let buffer = stdout.clone();
+        let write = ctx.create_function(
+            move |_, (this, args): (rlua::Table, rlua::Variadic<rlua::String>)| {
+                append(&buffer, &args);
+                Ok(this)
+            },
+        )?;
+        proxy.set("write", write)?;
+        let key = ctx.create_registry_value(proxy.clone())?;
+        let buffer = stdout.clone();
+        let write = ctx.create_function(move |ctx, args: rlua::Variadic<rlua::String>| {
+            append(&buffer, &args);
+            ctx.registry_value::<rlua::Table>(&key)
+        })?;
+        io.set("write", write)?;
+        io.set("stdout", proxy)?;
+    }
+    Ok(())
 }
 
 /// # Runtime Host
@@ -26,27 +325,61 @@ pub struct EvaluationOutput {
 ///
 /// This host will take care of evaluating the incoming Saturnus code.
 #[deprecated(
-    since = "v0.2.0",
-    note = "We have to rethink how runtime is evaluated! - Saturnus's primary VM will be always Lua."
+    since = "0.2.0",
+    note = "Use `saturnus::Engine` to embed and evaluate Saturnus scripts."
 )]
 pub struct RuntimeHost {
     host: rlua::Lua,
     compiler: Box<dyn Visitor>,
     indent: String,
+    sandbox: Option<Sandbox>,
+    stdout: Arc<Mutex<String>>,
+    builtins: HashSet<String>,
 }
 
+#[allow(deprecated)]
 impl RuntimeHost {
     pub fn new(indent: String, compiler: Box<dyn Visitor>) -> RuntimeHost {
-        let host = rlua::Lua::new();
+        Self::from_lua(rlua::Lua::new(), indent, compiler, None)
+    }
+
+    /// Like `new`, but evaluates the code inside of the given sandbox.
+    pub fn with_sandbox(
+        indent: String,
+        compiler: Box<dyn Visitor>,
+        sandbox: Sandbox,
+    ) -> RuntimeHost {
+        Self::from_lua(sandbox.create_lua(), indent, compiler, Some(sandbox))
+    }
+
+    fn from_lua(
+        host: rlua::Lua,
+        indent: String,
+        compiler: Box<dyn Visitor>,
+        sandbox: Option<Sandbox>,
+    ) -> RuntimeHost {
+        let stdout = Arc::new(Mutex::new(String::new()));
+        let builtins = host
+            .context(|ctx| -> rlua::Result<HashSet<String>> {
+                redirect_output(ctx, &stdout)?;
+                ctx.globals()
+                    .pairs::<String, rlua::Value>()
+                    .map(|pair| pair.map(|(name, _)| name))
+                    .collect()
+            })
+            .expect("Could not prepare the runtime host");
         RuntimeHost {
             host,
             indent,
             compiler,
+            sandbox,
+            stdout,
+            builtins,
         }
     }
 
     pub fn run(&self, code: &String) -> Result<EvaluationOutput, RuntimeError> {
-        let parsed = Script::parse(code).map_err(|err| RuntimeError::ParseError(err))?;
+        let parsed = Script::parse(code).map_err(RuntimeError::ParseError)?;
         self.evaluate(&parsed)
     }
 
@@ -54,18 +387,42 @@ impl RuntimeHost {
     pub fn evaluate(&self, script: &Script) -> Result<EvaluationOutput, RuntimeError> {
         let code = self
             .compiler
-            .visit_script(Builder::new(self.indent.clone()), &script)
-            .map_err(|err| RuntimeError::CompilationError(err))?
+            .visit_script(Builder::new(self.indent.clone()), script)
+            .map_err(RuntimeError::CompilationError)?
             .collect();
-        self.host
-            .context(move |ctx| -> rlua::Result<()> {
-                ctx.load(&code).eval()?;
-                Ok(())
-            })
-            .map_err(|err| RuntimeError::EvaluationError(err))?;
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.guard(&self.host);
+        }
+        let result = self.host.context(|ctx| -> rlua::Result<_> {
+            let value = ctx.load(&code).call::<_, rlua::Value>(())?;
+            let value = Value::from_lua(ctx, value)?;
+            let vars = ctx
+                .globals()
+                .pairs::<rlua::Value, rlua::Value>()
+                .filter_map(|pair| match pair {
+                    Ok((rlua::Value::String(name), value)) => {
+                        let name = name.to_str().ok()?.to_owned();
+                        if self.builtins.contains(&name) {
+                            return None;
+                        }
+                        Some(Value::from_lua(ctx, value).map(|value| (name, value)))
+                    }
+                    Ok(_) => None,
+                    Err(err) => Some(Err(err)),
+                })
+                .collect::<rlua::Result<_>>()?;
+            Ok((value, vars))
+        });
+        // The output is taken even on failure, so it doesn't leak into the next run.
+        let stdout = std::mem::take(&mut *self.stdout.lock().unwrap());
+        let (value, vars) = result.map_err(|err| match &self.sandbox {
+            Some(sandbox) => sandbox.map_error(err),
+            None => RuntimeError::EvaluationError(err),
+        })?;
         Ok(EvaluationOutput {
-            stdout: "".into(),
-            vars: (),
+            stdout,
+            value,
+            vars,
         })
     }
 }
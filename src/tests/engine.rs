@@ -0,0 +1,276 @@
+use rlua::{UserData, UserDataMethods};
+
+use crate::{
+    engine::{MemoryResolver, ModuleResolver},
+    runtime::RuntimeError,
+    Engine,
+};
+
+#[test]
+fn test_eval_return_value() {
+    let engine = Engine::new().unwrap();
+    let value: i64 = engine.eval("let a = 20; return a + 22;").unwrap();
+    assert_eq!(value, 42);
+}
+
+#[test]
+fn test_compile_is_cached() {
+    let engine = Engine::new().unwrap();
+    let src = "let a = 1;";
+    assert_eq!(engine.compile(src).unwrap(), engine.compile(src).unwrap());
+    assert_eq!(engine.cache.borrow().len(), 1);
+}
+
+#[test]
+fn test_compile_cache_depends_on_path() {
+    let mut engine = Engine::new().unwrap();
+    let src = "return file!();";
+    let first = engine.compile(src).unwrap();
+    engine.set_script_path("other.saturn");
+    let second = engine.compile(src).unwrap();
+    assert_ne!(first, second);
+    assert!(second.contains("other.saturn"));
+}
+
+#[test]
+fn test_register_fn_and_call() {
+    let engine = Engine::new().unwrap();
+    engine
+        .register_fn("add", |(a, b): (i64, i64)| Ok(a + b))
+        .unwrap();
+    engine
+        .exec("fn twice(x) = add(x, x); double = twice;")
+        .unwrap();
+    let value: i64 = engine.call("double", 21).unwrap();
+    assert_eq!(value, 42);
+}
+
+#[test]
+fn test_globals() {
+    let engine = Engine::new().unwrap();
+    engine.set_global("greeting", "Hello").unwrap();
+    engine.exec("message = greeting ++ \" World!\";").unwrap();
+    let message: String = engine.get_global("message").unwrap();
+    assert_eq!(message, "Hello World!");
+}
+
+struct Counter(i64);
+impl UserData for Counter {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("bump", |_, this, ()| {
+            this.0 += 1;
+            Ok(this.0)
+        });
+    }
+}
+
+#[test]
+fn test_userdata() {
+    let engine = Engine::new().unwrap();
+    engine.set_global("counter", Counter(0)).unwrap();
+    let value: i64 = engine
+        .eval("counter->bump(); return counter->bump();")
+        .unwrap();
+    assert_eq!(value, 2);
+}
+
+#[test]
+fn test_std_is_available() {
+    let engine = Engine::new().unwrap();
+    let value: i64 = engine
+        .eval("use { Vector } in std; let v = Vector.new([1, 2]); return v->len();")
+        .unwrap();
+    assert_eq!(value, 2);
+}
+
+#[test]
+fn test_custom_resolver() {
+    let engine = Engine::new().unwrap();
+    let resolver =
+        MemoryResolver::new().with_module("game.math", "fn square(x) = x * x; return { square };");
+    assert!(resolver.resolve("game.math").is_some());
+    engine.set_resolver(resolver).unwrap();
+    let value: i64 = engine
+        .eval("use { square } in game.math; return square(7);")
+        .unwrap();
+    assert_eq!(value, 49);
+}
+
+#[test]
+fn test_set_resolver_replaces_the_previous_one() {
+    let engine = Engine::new().unwrap();
+    let searchers = |engine: &Engine| {
+        engine.context(|ctx| ctx.load("return #package.searchers").eval::<i64>().unwrap())
+    };
+    let before = searchers(&engine);
+    engine
+        .set_resolver(MemoryResolver::new().with_module("first", "return 1;"))
+        .unwrap();
+    engine
+        .set_resolver(MemoryResolver::new().with_module("second", "return 2;"))
+        .unwrap();
+    assert_eq!(searchers(&engine), before + 1);
+    let value: i64 = engine.eval("return require(\"second\");").unwrap();
+    assert_eq!(value, 2);
+    assert!(engine.exec("require(\"first\");").is_err());
+}
+
+#[test]
+fn test_resolver_reports_parse_errors() {
+    let engine = Engine::new().unwrap();
+    engine
+        .set_resolver(MemoryResolver::new().with_module("broken", "let = ;"))
+        .unwrap();
+    let result = engine.exec("use broken;");
+    assert!(matches!(result, Err(RuntimeError::EvaluationError(_))));
+}
+
+#[test]
+fn test_pub_items_are_exported() {
+    let engine = Engine::new().unwrap();
+    let lib = "
+        pub fn square(x) = x * x;
+        pub class Point {}
+        pub enum Shape { Circle(radius), Square(side) }
+        pub let { origin, unit } = { origin: 0, unit: 1 };
+        let hidden = 42;
+        fn helper() = hidden;
+    ";
+    engine
+        .set_resolver(MemoryResolver::new().with_module("geometry", lib))
+        .unwrap();
+    let value: i64 = engine
+        .eval("use { square, Point, Shape, origin, unit } in geometry; return square(7) + unit;")
+        .unwrap();
+    assert_eq!(value, 50);
+    let exported: bool = engine
+        .eval("use geometry; return geometry.hidden == () and geometry.helper == ();")
+        .unwrap();
+    assert!(exported);
+}
+
+#[test]
+fn test_use_checks_exports() {
+    let engine = Engine::new().unwrap();
+    engine
+        .set_resolver(MemoryResolver::new().with_module("lib", "pub fn shown() = 1;"))
+        .unwrap();
+    let Err(RuntimeError::EvaluationError(err)) = engine.exec("use { shown, hidden } in lib;")
+    else {
+        panic!("Expected a missing export error");
+    };
+    assert!(err
+        .to_string()
+        .contains("Module lib does not export hidden"));
+    // Names with a default value may be missing.
+    let value: i64 = engine
+        .eval("use { shown, hidden = 2 } in lib; return shown() + hidden;")
+        .unwrap();
+    assert_eq!(value, 3);
+}
+
+#[test]
+fn test_pub_items_cant_mix_with_return() {
+    let engine = Engine::new().unwrap();
+    let result = engine.compile("pub fn a() = 1; return { a };");
+    assert!(matches!(result, Err(RuntimeError::CompilationError(_))));
+    let result = engine.compile("pub fn a() = 1; if a() == 1 { return {}; }");
+    assert!(matches!(result, Err(RuntimeError::CompilationError(_))));
+    // Returning from functions is fine.
+    assert!(engine.compile("pub fn a() { return 1; }").is_ok());
+}
+
+#[test]
+fn test_pub_items_only_at_top_level() {
+    let engine = Engine::new().unwrap();
+    for src in [
+        "fn f() { pub let a = 1; }",
+        "if true { pub fn a() = 1; }",
+        "let f = () => { pub let a = 1; };",
+    ] {
+        let result = engine.compile(src);
+        assert!(
+            matches!(result, Err(RuntimeError::CompilationError(_))),
+            "{src}"
+        );
+    }
+}
+
+#[test]
+fn test_number_overflow_is_a_parse_error() {
+    let engine = Engine::new().unwrap();
+    for src in [
+        "let a = 99999999999999999999;",
+        "let a = 1e999;",
+        "let a = 0x1_0000_0000_0000_0000;",
+    ] {
+        let result = engine.exec(src);
+        assert!(matches!(result, Err(RuntimeError::ParseError(_))), "{src}");
+    }
+}
+
+#[test]
+fn test_catch_binds_the_traceback() {
+    let src = r#"
+    fn fails() {
+      error({ code: 42 });
+    }
+    try {
+      fails();
+    } catch (e, traceback) {
+      code = e.code;
+      trace = traceback;
+    }
+    "#;
+    let engine = Engine::new().unwrap();
+    engine.exec(src).unwrap();
+    assert_eq!(engine.get_global::<i64>("code").unwrap(), 42);
+    let traceback: String = engine.get_global("trace").unwrap();
+    assert!(traceback.starts_with("stack traceback:\n\t"));
+    // With the debug library, its own traceback is used.
+    let lua = unsafe { rlua::Lua::unsafe_new_with(rlua::StdLib::ALL) };
+    let engine = Engine::from_lua(lua).unwrap();
+    engine.exec(src).unwrap();
+    assert_eq!(engine.get_global::<i64>("code").unwrap(), 42);
+    let traceback: String = engine.get_global("trace").unwrap();
+    assert!(traceback.contains("in function"));
+}
+
+#[test]
+fn test_exits_inside_if_expressions() {
+    let engine = Engine::new().unwrap();
+    // Statements and let bindings are left by their branches.
+    assert!(engine
+        .compile("fn f(c) { let x = if c { return 1; } else { 2 }; return x; }")
+        .is_ok());
+    assert!(engine
+        .compile("loop { let x = if true { break; } else { 2 }; }")
+        .is_ok());
+    // Other if expressions would only leave the function they're wrapped in.
+    assert!(engine
+        .compile("fn f(c) { print(if c { return 1; } else { 2 }); }")
+        .is_err());
+    assert!(engine
+        .compile("loop { print(if true { break; } else { 2 }); }")
+        .is_err());
+    assert!(engine
+        .compile("fn f(c) { print(if c { try { return 1; } catch {} 1 } else { 2 }); }")
+        .is_err());
+    // Unless they leave a function or a loop of the branch itself.
+    assert!(engine
+        .compile("print(if true { loop { break; } let f = () => { return 1; }; f() } else { 2 });")
+        .is_ok());
+}
+
+#[test]
+fn test_yield_only_inside_generators() {
+    let engine = Engine::new().unwrap();
+    assert!(engine.compile("fn* f() { yield 1; }").is_ok());
+    // Async functions yield to whoever steps their task.
+    assert!(engine.compile("async fn f() { yield; }").is_ok());
+    assert!(engine.compile("yield 1;").is_err());
+    assert!(engine.compile("fn f() { yield 1; }").is_err());
+    assert!(engine
+        .compile("fn* f() { return () => { yield 1; }; }")
+        .is_err());
+}
@@ -0,0 +1,180 @@
+use crate::{
+    errors::report_error,
+    lint::{lint, Level, LintConfig, Rule},
+    parser::Script,
+};
+
+/// The rules broken by the script, in reporting order.
+fn broken(config: LintConfig, src: &str) -> Vec<Rule> {
+    let script = Script::parse(src).unwrap();
+    lint(&script, &config)
+        .unwrap()
+        .into_iter()
+        .map(|lint| lint.rule)
+        .collect()
+}
+
+fn all_rules() -> LintConfig {
+    Rule::ALL
+        .into_iter()
+        .fold(LintConfig::new(), |config, rule| {
+            config.level(rule, Level::Warn)
+        })
+}
+
+#[test]
+fn test_unused_variables() {
+    let src = "
+    use { Vector } in std;
+    let used = 1;
+    let { a, _b } = { a: 1, _b: 2 };
+    fn f(ignored) { return used; }
+    pub let exported = 2;
+    ";
+    assert_eq!(
+        broken(all_rules(), src),
+        [Rule::UnusedVariables, Rule::UnusedVariables]
+    );
+    let lints = lint(&Script::parse(src).unwrap(), &LintConfig::new()).unwrap();
+    assert_eq!(lints[0].message, "`Vector` is never read");
+    assert_eq!(lints[1].message, "`a` is never read");
+}
+
+#[test]
+fn test_extern_code_reads_bindings() {
+    let src = "
+    fn keys(tbl) {
+      let seen = {};
+      <extern \"Lua\">
+        return pairs(seen);
+      </extern>
+    }
+    ";
+    assert!(broken(all_rules(), src).is_empty());
+}
+
+#[test]
+fn test_shadowing_is_opt_in() {
+    let src = "
+    let x = 1;
+    fn f(x) { return x; }
+    print(x);
+    ";
+    assert!(broken(LintConfig::new(), src).is_empty());
+    assert_eq!(broken(all_rules(), src), [Rule::Shadowing]);
+}
+
+#[test]
+fn test_unreachable_code() {
+    let src = "
+    fn f() {
+      return 1;
+      print(\"never\");
+    }
+    ";
+    let lints = lint(&Script::parse(src).unwrap(), &LintConfig::new()).unwrap();
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].rule, Rule::UnreachableCode);
+    assert_eq!(lints[0].message, "Unreachable code after `return`, in `f`");
+}
+
+#[test]
+fn test_undeclared_assignment() {
+    let src = "
+    let declared = 1;
+    declared = 2;
+    created = declared;
+    host = 3;
+    print(created);
+    ";
+    assert_eq!(broken(all_rules(), src), [Rule::UndeclaredAssignment; 2]);
+    let config = all_rules().allow_global("created").allow_global("host");
+    assert!(broken(config, src).is_empty());
+}
+
+#[test]
+fn test_nil_comparison() {
+    let src = "
+    fn f(a, b) {
+      if a == () { a = b; }
+      let c = if b <> () { b } else { 1 };
+      let d = if a.x == () { c } else { a.x };
+      assert(d == ());
+      return d;
+    }
+    ";
+    assert_eq!(broken(all_rules(), src), [Rule::NilComparison; 3]);
+}
+
+#[test]
+fn test_empty_blocks() {
+    let src = "
+    fn noop() {}
+    loop {}
+    try { noop(); } catch (e) {}
+    let x = true;
+    if x {}
+    while x { }
+    for _i in x { }
+    ";
+    assert_eq!(broken(all_rules(), src), [Rule::EmptyBlocks; 5]);
+}
+
+#[test]
+fn test_lints_are_located() {
+    let src = "let unused = 1;\nfn f() {\n  return 1;\n  print(missing);\n}";
+    let lints = lint(&Script::parse(src).unwrap(), &all_rules()).unwrap();
+    let located = lints
+        .iter()
+        .map(|lint| (lint.rule, lint.line_col(src)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        located,
+        [
+            (Rule::UnreachableCode, (4, 3)),
+            (Rule::UnusedVariables, (1, 1)),
+            (Rule::UndeclaredNames, (4, 3)),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_errors_at_line_start_are_reported() {
+    let src = "let x = 1;\n}";
+    let err = Script::parse(src).unwrap_err();
+    let report = report_error("main.saturn".into(), src.into(), err);
+    assert!(report.contains("At main.saturn:2:1"));
+}
+
+#[test]
+fn test_allow_decorator() {
+    let src = "
+    #[allow(unused_variables)]
+    let unused = 1;
+    #[allow(empty_blocks, undeclared_names)]
+    try { missing(); } catch (e) {}
+    try { missing(); } catch (e) {}
+    ";
+    assert_eq!(
+        broken(all_rules(), src),
+        [Rule::EmptyBlocks, Rule::UndeclaredNames]
+    );
+}
+
+#[test]
+fn test_levels() {
+    let src = "let unused = 1; loop {}";
+    let config = LintConfig::new()
+        .level(Rule::UnusedVariables, Level::Deny)
+        .level(Rule::EmptyBlocks, Level::Allow);
+    let lints = lint(&Script::parse(src).unwrap(), &config).unwrap();
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].level, Level::Deny);
+    assert_eq!(
+        lints[0].to_string(),
+        "error[unused_variables]: `unused` is never read"
+    );
+    assert_eq!("warn".parse::<Level>(), Ok(Level::Warn));
+    assert!("loud".parse::<Level>().is_err());
+    assert_eq!(Rule::from_name("nil_comparison"), Some(Rule::NilComparison));
+}
@@ -1,75 +1,242 @@
-use crate::{lua::visitor::LuaEmitter, runtime::RuntimeHost};
+mod engine;
+mod lint;
+mod modules;
+mod runtime;
+mod sandbox;
+mod scheduler;
+mod scope_analysis;
+mod std_usage;
 
-fn get_rt() -> RuntimeHost {
-    RuntimeHost::new("  ".into(), Box::new(LuaEmitter::new()))
+use crate::Engine;
+
+fn get_rt() -> Engine {
+    Engine::new().unwrap()
 }
 
 #[test]
 fn test_basic_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/basic.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_class_decorators_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/class_decorators.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_collections_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/collections.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_destructuring_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/destructuring.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_extra_op_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/extra_op.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_function_decorators_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/function_decorators.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_hello_world_oop_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/hello_world_oop.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_js_style_programs_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/js_style_programs.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_loops_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/loops.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
 }
 
 #[test]
 fn test_oop_example() {
     let rt = get_rt();
     let src = include_str!("../../examples/oop.saturn").to_owned();
-    rt.run(&src).unwrap();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_inheritance_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/inheritance.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_operator_overloading_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/operator_overloading.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_unary_minus_overloading() {
+    let rt = get_rt();
+    let src = r#"
+class Money {
+  fn `-`(self) = Money { amount: -self.amount };
+}
+let debt = -Money { amount: 5 };
+assert(debt.amount == -5);
+"#
+    .to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_accessors_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/accessors.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_class_fields_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/class_fields.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_class_field_defaults_scope() {
+    let rt = get_rt();
+    let src = r#"
+let struct = "outer";
+let self = "outer";
+class Named {
+  let name = struct ++ self;
+}
+assert(Named {}.name == "outerouter");
+"#
+    .to_owned();
+    rt.exec(&src).unwrap();
+    assert!(rt.compile("class A { let { a } = {}; }").is_err());
+    assert!(rt.compile("class A { static let { a } = {}; }").is_err());
+}
+
+#[test]
+fn test_enums_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/enums.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_optional_chaining_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/optional_chaining.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_string_interpolation_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/string_interpolation.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_strings_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/strings.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_numbers_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/numbers.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_hex_range() {
+    let rt = get_rt();
+    let src = r#"
+use { operators: { `..` } } in std;
+let total = 0;
+for i in 0x10..0x12 {
+  total += i;
+}
+assert(total == 51);
+"#
+    .to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_default_arguments_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/default_arguments.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_destructuring_patterns_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/destructuring_patterns.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_if_expressions_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/if_expressions.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_try_catch_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/try_catch.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_defer_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/defer.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_generators_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/generators.saturn").to_owned();
+    rt.exec(&src).unwrap();
+}
+
+#[test]
+fn test_async_example() {
+    let rt = get_rt();
+    let src = include_str!("../../examples/async.saturn").to_owned();
+    rt.exec(&src).unwrap();
 }
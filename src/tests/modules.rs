@@ -0,0 +1,155 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    code::{ast_visitor::Visitor, builder::Builder, info::InputFileInfo},
+    engine::{FileSystemResolver, UnresolvedModule},
+    lua::{bundle::Bundler, visitor::LuaEmitter},
+    parser::Script,
+};
+
+fn compile(src: &str) -> Result<String, String> {
+    let compiler = LuaEmitter::new(InputFileInfo {
+        full_path: PathBuf::from("examples/main.saturn"),
+    })
+    .with_resolver(FileSystemResolver::new(["examples"]));
+    let script = Script::parse(src).unwrap();
+    compiler
+        .visit_script(Builder::new("  "), &script)
+        .map(Builder::collect)
+        .map_err(|err| err.0.to_string())
+}
+
+#[test]
+fn test_resolves_modules_in_roots() {
+    let code = compile("use std; use { foo } in com.bar;").unwrap();
+    assert!(code.contains("require(\"com.bar\")"));
+}
+
+#[test]
+fn test_unresolved_module_lists_searched_paths() {
+    let err = compile("use com.baz;").unwrap_err();
+    let expected = UnresolvedModule {
+        module: "com.baz".into(),
+        searched: vec![
+            PathBuf::from("examples/com/baz.saturn"),
+            PathBuf::from("examples/com/baz.lua"),
+            PathBuf::from("examples/com/baz/init.saturn"),
+            PathBuf::from("examples/com/baz/init.lua"),
+        ],
+    };
+    assert_eq!(err, expected.to_string());
+    assert!(err.starts_with("Module com.baz not found, searched:"));
+}
+
+#[test]
+fn test_lua_libraries_need_no_file() {
+    let code = compile("use math; use { insert } in table;").unwrap();
+    assert!(code.contains("require(\"math\")"));
+}
+
+#[test]
+fn test_unresolved_modules_can_be_left_to_lua() {
+    let compiler = LuaEmitter::new(InputFileInfo {
+        full_path: PathBuf::from("examples/main.saturn"),
+    })
+    .with_resolver(FileSystemResolver::new(["examples"]))
+    .with_unresolved_allowed(true);
+    let script = Script::parse("use lfs; use com.bar;").unwrap();
+    let code = compiler
+        .visit_script(Builder::new("  "), &script)
+        .unwrap()
+        .collect();
+    assert!(code.contains("require(\"lfs\")"));
+    let unresolved = compiler.unresolved_modules.borrow();
+    assert_eq!(unresolved.len(), 1);
+    assert_eq!(unresolved[0].module, "lfs");
+    assert_eq!(*compiler.used_modules.borrow(), ["com.bar"]);
+}
+
+/// Bundles the entry with the modules found under a fresh temporary root.
+fn bundle(modules: &[(&str, &str)], entry: &str) -> String {
+    static NEXT_ROOT: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "saturnus-bundle-{}-{}",
+        std::process::id(),
+        NEXT_ROOT.fetch_add(1, Ordering::Relaxed)
+    ));
+    for (name, source) in modules {
+        let path = root.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+    let resolver = FileSystemResolver::new([&root]);
+    let compiler = LuaEmitter::new(InputFileInfo {
+        full_path: root.join("main.saturn"),
+    })
+    .with_resolver(resolver.clone());
+    let main = compiler
+        .visit_script(Builder::new("  "), &Script::parse(entry).unwrap())
+        .unwrap()
+        .collect();
+    let modules = Bundler::new(resolver, "  ").bundle(&compiler).unwrap();
+    std::fs::remove_dir_all(root).unwrap();
+    format!("{modules}\n{main}")
+}
+
+#[test]
+fn test_bundle_includes_reachable_modules_once() {
+    let code = bundle(
+        &[
+            ("app/a.saturn", "use { suffix } in app.b; pub fn greet(n) = \"hi \" ++ n ++ suffix;"),
+            ("app/b.saturn", "pub let suffix = \"!\";"),
+            ("app/c.saturn", "use app.b; use app.a; pub let shout = a.greet(\"c\") ++ b.suffix;"),
+            ("app/unused.saturn", "pub let nope = 1;"),
+            ("native.lua", "return { answer = 42 };"),
+        ],
+        "use { shout } in app.c; use { greet } in app.a; use native; return greet(\"x\") ++ shout ++ native.answer;",
+    );
+    assert_eq!(code.matches("package.preload[\"app.b\"] =").count(), 1);
+    assert_eq!(code.matches("package.preload[\"app.a\"] =").count(), 1);
+    assert!(!code.contains("app.unused"));
+    // Dependencies are defined before the modules that use them.
+    assert!(code.find("\"app.b\"] =").unwrap() < code.find("\"app.a\"] =").unwrap());
+    let value: String = rlua::Lua::new()
+        .context(|ctx| ctx.load(&code).eval())
+        .unwrap();
+    assert_eq!(value, "hi x!hi c!!42");
+}
+
+#[test]
+fn test_bundle_keeps_similar_module_names_apart() {
+    let code = bundle(
+        &[
+            ("a/b.saturn", "pub let name = \"a.b\";"),
+            ("a_b.saturn", "pub let name = \"a_b\";"),
+        ],
+        "use a.b; use a_b; return b.name ++ \" \" ++ a_b.name;",
+    );
+    let loaders = code
+        .lines()
+        .filter_map(|line| line.strip_prefix("local __saturnus_module_"))
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(loaders.len(), 2);
+    let value: String = rlua::Lua::new()
+        .context(|ctx| ctx.load(&code).eval())
+        .unwrap();
+    assert_eq!(value, "a.b a_b");
+}
+
+#[test]
+fn test_exports_survive_top_level_defer() {
+    let code = bundle(
+        &[(
+            "m1.saturn",
+            "log = []; defer table.insert(log, \"deferred\"); pub fn f() = \"f\"; pub let x = 1;",
+        )],
+        "use { f, x } in m1; return f() ++ x ++ log[1];",
+    );
+    let value: String = rlua::Lua::new()
+        .context(|ctx| ctx.load(&code).eval())
+        .unwrap();
+    assert_eq!(value, "f1deferred");
+}
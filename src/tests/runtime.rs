@@ -0,0 +1,76 @@
+#![allow(deprecated)]
+
+use std::path::PathBuf;
+
+use crate::{
+    code::info::InputFileInfo,
+    lua::visitor::LuaEmitter,
+    runtime::{RuntimeHost, Value},
+};
+
+fn get_host() -> RuntimeHost {
+    let compiler = LuaEmitter::new(InputFileInfo {
+        full_path: PathBuf::from("test.saturn"),
+    });
+    RuntimeHost::new("  ".into(), Box::new(compiler))
+}
+
+#[test]
+fn test_captures_stdout() {
+    let host = get_host();
+    let output = host
+        .run(&r#"print("Hello", 42); io.write("a", 1); io.write("b");"#.into())
+        .unwrap();
+    assert_eq!(output.stdout, "Hello\t42\na1b");
+    // Each evaluation only sees its own output.
+    let output = host.run(&"print(true);".into()).unwrap();
+    assert_eq!(output.stdout, "true\n");
+}
+
+#[test]
+fn test_captures_chained_writes() {
+    let host = get_host();
+    let output = host
+        .run(&r#"io.write("a")->write("b", 2)->write("c"); io.stdout->write("d");"#.into())
+        .unwrap();
+    assert_eq!(output.stdout, "ab2cd");
+}
+
+#[test]
+fn test_return_value() {
+    let host = get_host();
+    let output = host
+        .run(&"return { name: \"foo\", size: 3 };".into())
+        .unwrap();
+    assert_eq!(output.value.get("name"), Some(&Value::String("foo".into())));
+    assert_eq!(output.value.get("size"), Some(&Value::Integer(3)));
+    let output = host.run(&"let a = 1;".into()).unwrap();
+    assert_eq!(output.value, Value::Nil);
+}
+
+#[test]
+fn test_global_vars() {
+    let host = get_host();
+    let output = host
+        .run(&"counter = 1.5; items = [true]; items[2] = items; let local_one = 1;".into())
+        .unwrap();
+    assert_eq!(output.vars.len(), 2);
+    assert_eq!(output.vars["counter"], Value::Number(1.5));
+    assert_eq!(
+        output.vars["items"],
+        Value::Table(vec![
+            (Value::Integer(1), Value::Boolean(true)),
+            (Value::Integer(2), Value::Opaque("table")),
+        ])
+    );
+}
+
+#[test]
+fn test_non_utf8_strings() {
+    let host = get_host();
+    let output = host
+        .run(&"let s = string.char(104, 255); print(s); io.write(s); return s;".into())
+        .unwrap();
+    assert_eq!(output.stdout, "h\u{FFFD}\nh\u{FFFD}");
+    assert_eq!(output.value, Value::String("h\u{FFFD}".into()));
+}
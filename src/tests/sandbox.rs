@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use crate::{engine::Sandbox, runtime::RuntimeError, Engine};
+
+#[test]
+fn test_unsafe_globals_are_removed() {
+    let engine = Engine::sandboxed(Sandbox::new()).unwrap();
+    for expr in ["io", "os", "load", "dofile"] {
+        let removed: bool = engine.eval(&format!("return {expr} == ();")).unwrap();
+        assert!(removed, "{expr} should not be available");
+    }
+}
+
+#[test]
+fn test_os_is_stripped_when_allowed() {
+    let sandbox = Sandbox::new().libs(rlua::StdLib::ALL);
+    let engine = Engine::sandboxed(sandbox).unwrap();
+    let clock: bool = engine.eval("return os.clock <> ();").unwrap();
+    let execute: bool = engine.eval("return os.execute == ();").unwrap();
+    assert!(clock && execute);
+}
+
+#[test]
+fn test_blocked_libraries_cant_be_required() {
+    let sandbox = Sandbox::new().libs(rlua::StdLib::ALL);
+    let engine = Engine::sandboxed(sandbox).unwrap();
+    let unloaded: bool = engine.eval("return package.loaded.io == ();").unwrap();
+    assert!(unloaded);
+    let stripped: bool = engine
+        .eval("return package.loaded.os.execute == ();")
+        .unwrap();
+    assert!(stripped);
+    assert!(engine.exec("require(\"io\");").is_err());
+}
+
+#[test]
+fn test_std_is_still_available() {
+    let engine = Engine::sandboxed(Sandbox::new()).unwrap();
+    let value: i64 = engine
+        .eval(
+            "use { Object } in std; let n = 0; for e in Object.entries({ a: 1, b: 2 }) { n += 1; } return n;",
+        )
+        .unwrap();
+    assert_eq!(value, 2);
+}
+
+#[test]
+fn test_instruction_limit() {
+    let engine = Engine::sandboxed(Sandbox::new().instruction_limit(100_000)).unwrap();
+    let err = engine.exec("loop {}").unwrap_err();
+    assert!(matches!(
+        err,
+        RuntimeError::InstructionLimitExceeded(100_000)
+    ));
+    // The budget is reset on each evaluation.
+    engine.exec("let a = 1 + 1;").unwrap();
+}
+
+#[test]
+fn test_timeout() {
+    let engine = Engine::sandboxed(Sandbox::new().timeout(Duration::from_millis(20))).unwrap();
+    let err = engine.exec("loop {}").unwrap_err();
+    assert!(matches!(err, RuntimeError::TimeoutExceeded(_)));
+}
+
+#[test]
+fn test_memory_limit() {
+    let engine = Engine::sandboxed(Sandbox::new().memory_limit(4 * 1024 * 1024)).unwrap();
+    let err = engine
+        .exec("let t = []; loop { t[#?t + 1] = \"chunk\" ++ #?t; }")
+        .unwrap_err();
+    assert!(
+        matches!(err, RuntimeError::MemoryLimitExceeded(_)),
+        "{err:?}"
+    );
+}
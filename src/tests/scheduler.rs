@@ -0,0 +1,193 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Engine;
+
+/// An engine whose `Task.sleep` reads the returned clock instead of the
+/// system's, so tests decide when time passes.
+fn fake_clock_engine() -> (Engine, Arc<Mutex<f64>>) {
+    let engine = Engine::new().unwrap();
+    let now = Arc::new(Mutex::new(0.0));
+    let clock = now.clone();
+    engine.set_clock(move || *clock.lock().unwrap()).unwrap();
+    (engine, now)
+}
+
+fn log(engine: &Engine) -> Vec<String> {
+    engine.eval("return table.concat(log, \",\");").map_or_else(
+        |err| panic!("{err:?}"),
+        |log: String| {
+            log.split(',')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        },
+    )
+}
+
+const WORKERS: &str = r#"
+use { Task } in std;
+log = [];
+async fn worker(name, delay) {
+  await Task.sleep(delay);
+  log[#?log + 1] = name;
+  return delay;
+}
+"#;
+
+#[test]
+fn test_sleep_waits_for_the_clock() {
+    let (engine, now) = fake_clock_engine();
+    engine
+        .exec(&format!(
+            "{WORKERS}
+            Task.spawn(worker(\"slow\", 2));
+            Task.spawn(worker(\"fast\", 1));"
+        ))
+        .unwrap();
+    assert_eq!(engine.tick().unwrap(), 2);
+    assert_eq!(engine.tick().unwrap(), 2);
+    assert!(log(&engine).is_empty());
+    *now.lock().unwrap() = 1.0;
+    assert_eq!(engine.tick().unwrap(), 1);
+    assert_eq!(log(&engine), ["fast"]);
+    *now.lock().unwrap() = 5.0;
+    assert_eq!(engine.tick().unwrap(), 0);
+    assert_eq!(log(&engine), ["fast", "slow"]);
+}
+
+#[test]
+fn test_join_waits_for_every_task() {
+    let (engine, now) = fake_clock_engine();
+    engine
+        .exec(&format!(
+            "{WORKERS}
+            async fn main() {{
+              let results = await Task.join(worker(\"a\", 3), worker(\"b\", 1));
+              log[#?log + 1] = \"sum \" ++ (results[1] + results[2]);
+            }}
+            Task.spawn(main());"
+        ))
+        .unwrap();
+    // The workers are spawned by `join`, and run from the next tick on.
+    assert_eq!(engine.tick().unwrap(), 3);
+    assert_eq!(engine.tick().unwrap(), 3);
+    *now.lock().unwrap() = 1.0;
+    assert_eq!(engine.tick().unwrap(), 2);
+    assert_eq!(log(&engine), ["b"]);
+    *now.lock().unwrap() = 3.0;
+    assert_eq!(engine.tick().unwrap(), 1);
+    assert_eq!(log(&engine), ["b", "a"]);
+    assert_eq!(engine.tick().unwrap(), 0);
+    assert_eq!(log(&engine), ["b", "a", "sum 4"]);
+}
+
+#[test]
+fn test_select_returns_the_first_finished_task() {
+    let (engine, now) = fake_clock_engine();
+    engine
+        .exec(&format!(
+            "{WORKERS}
+            async fn main() {{
+              let (i, value) = await Task.select(worker(\"a\", 3), worker(\"b\", 1));
+              log[#?log + 1] = \"first \" ++ i ++ \" \" ++ value;
+            }}
+            Task.spawn(main());"
+        ))
+        .unwrap();
+    assert_eq!(engine.tick().unwrap(), 3);
+    assert_eq!(engine.tick().unwrap(), 3);
+    *now.lock().unwrap() = 2.0;
+    assert_eq!(engine.tick().unwrap(), 2);
+    assert_eq!(engine.tick().unwrap(), 1);
+    assert_eq!(log(&engine), ["b", "first 2 1"]);
+    // The other task is still scheduled.
+    *now.lock().unwrap() = 3.0;
+    assert_eq!(engine.tick().unwrap(), 0);
+    assert_eq!(log(&engine), ["b", "first 2 1", "a"]);
+}
+
+#[test]
+fn test_join_leaves_stepping_to_the_scheduler() {
+    let (engine, _) = fake_clock_engine();
+    engine
+        .exec(
+            r#"
+            use { Task } in std;
+            log = [];
+            async fn count(name) {
+              log[#?log + 1] = name ++ 1;
+              yield;
+              log[#?log + 1] = name ++ 2;
+            }
+            async fn main() {
+              let a = Task.spawn(count("a"));
+              await Task.join(a, count("b"));
+              log[#?log + 1] = "joined";
+            }
+            Task.spawn(main());
+            "#,
+        )
+        .unwrap();
+    assert_eq!(engine.tick().unwrap(), 3);
+    assert!(log(&engine).is_empty());
+    // Each task is stepped once per tick, even the one spawned twice.
+    assert_eq!(engine.tick().unwrap(), 3);
+    assert_eq!(log(&engine), ["a1", "b1"]);
+    assert_eq!(engine.tick().unwrap(), 1);
+    assert_eq!(log(&engine), ["a1", "b1", "a2", "b2"]);
+    assert_eq!(engine.tick().unwrap(), 0);
+    assert_eq!(log(&engine), ["a1", "b1", "a2", "b2", "joined"]);
+}
+
+#[test]
+fn test_default_clock_is_the_wall_clock() {
+    let engine = Engine::new().unwrap();
+    let now: f64 = engine
+        .eval("use { Task } in std; return Task.now();")
+        .unwrap();
+    let expected = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    assert!((now - expected).abs() < 5.0);
+}
+
+#[test]
+fn test_await_raises_the_task_error() {
+    let (engine, _) = fake_clock_engine();
+    engine
+        .exec(
+            r#"
+            use { Task } in std;
+            async fn fails() {
+              error("boom", 0);
+            }
+            async fn main() {
+              try {
+                await fails();
+              } catch (e) {
+                caught = e;
+              }
+            }
+            Task.spawn(main());
+            "#,
+        )
+        .unwrap();
+    assert_eq!(engine.tick().unwrap(), 0);
+    let caught: String = engine.get_global("caught").unwrap();
+    assert_eq!(caught, "boom");
+}
+
+#[test]
+fn test_await_only_inside_async_functions() {
+    let engine = Engine::new().unwrap();
+    assert!(engine.compile("async fn f() { return await 1; }").is_ok());
+    assert!(engine.compile("let x = await 1;").is_err());
+    assert!(engine.compile("fn f() { return await 1; }").is_err());
+    assert!(engine
+        .compile("async fn f() { return () => await 1; }")
+        .is_err());
+}
@@ -0,0 +1,136 @@
+use crate::{
+    code::scope_analysis::{ScopeAnalyzer, Severity},
+    parser::Script,
+};
+
+/// The undeclared names read by the script, in reporting order.
+fn undeclared(analyzer: ScopeAnalyzer, src: &str) -> Vec<String> {
+    let script = Script::parse(src).unwrap();
+    analyzer
+        .analyze(&script)
+        .unwrap()
+        .into_iter()
+        .map(|diagnostic| diagnostic.name)
+        .collect()
+}
+
+#[test]
+fn test_reports_misspelled_names() {
+    let src = "
+    let total = 0;
+    fn add(value) { return totl + value; }
+    print(add(1), vlaue, totl);
+    ";
+    assert_eq!(undeclared(ScopeAnalyzer::new(), src), ["totl", "vlaue"]);
+}
+
+#[test]
+fn test_tracks_bindings() {
+    let src = "
+    use { Vector } in std;
+    use foo.bar;
+    let { a, b: [c, ...d], e = a } = bar;
+    let (x, y) = (a, c);
+    fn f(n, { k } = {}, *rest) { return f(n - 1, k, rest, d, x, y); }
+    class A {
+        fn new(self) { return Self { e }; }
+        static let zero = 0;
+    }
+    class B extends A {
+        fn new(self) { return super.new(self); }
+    }
+    for (i, v) in Vector.new() { print(i, v, B); }
+    try { f(1); } catch (err) { print(err); }
+    ";
+    assert!(undeclared(ScopeAnalyzer::new(), src).is_empty());
+}
+
+#[test]
+fn test_bindings_end_with_their_block() {
+    let src = "
+    if true { let inner = 1; }
+    let f = (arg) => arg;
+    print(inner, arg);
+    ";
+    assert_eq!(undeclared(ScopeAnalyzer::new(), src), ["inner", "arg"]);
+}
+
+#[test]
+fn test_loop_control_is_not_a_name() {
+    let src = "
+    let n = 0;
+    loop { n += 1; if n > 2 { break; } }
+    for i in ipairs([1, 2]) { if i > 1 { break; } }
+    while n > 0 { n -= 1; break; }
+    ";
+    assert!(undeclared(ScopeAnalyzer::new(), src).is_empty());
+    // Lua has no continue statement for it to compile to.
+    let src = "for i in ipairs([1, 2]) { if i == 1 { continue; } print(i); }";
+    assert_eq!(undeclared(ScopeAnalyzer::new(), src), ["continue"]);
+}
+
+#[test]
+fn test_allows_globals() {
+    let src = "
+    fn count() { return counter; }
+    counter = 0;
+    host_log(count(), string.format(\"%d\", 1));
+    ";
+    assert_eq!(undeclared(ScopeAnalyzer::new(), src), ["host_log"]);
+    let analyzer = ScopeAnalyzer::new().allow_global("host_log");
+    assert!(undeclared(analyzer, src).is_empty());
+}
+
+#[test]
+fn test_reports_operators_not_imported() {
+    let script = Script::parse("let x = 1 |> print;").unwrap();
+    let diagnostics = ScopeAnalyzer::new()
+        .severity(Severity::Error)
+        .analyze(&script)
+        .unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "error: Operator `|>` is not declared"
+    );
+    let src = "use { operators: { `|>` } } in std; let x = 1 |> print;";
+    assert!(undeclared(ScopeAnalyzer::new(), src).is_empty());
+}
+
+#[test]
+fn test_extern_declarations() {
+    let src = "
+    <extern \"Lua\">
+      local a, b = 1, 2;
+      local function helper() end
+      function shout(x) print(x) end
+    </extern>
+    print(a, b, helper, shout, c);
+    ";
+    assert_eq!(undeclared(ScopeAnalyzer::new(), src), ["c"]);
+}
+
+#[test]
+fn test_examples_declare_what_they_read() {
+    let examples = [
+        include_str!("../../examples/accessors.saturn"),
+        include_str!("../../examples/async.saturn"),
+        include_str!("../../examples/class_fields.saturn"),
+        include_str!("../../examples/collections.saturn"),
+        include_str!("../../examples/default_arguments.saturn"),
+        include_str!("../../examples/defer.saturn"),
+        include_str!("../../examples/destructuring_patterns.saturn"),
+        include_str!("../../examples/enums.saturn"),
+        include_str!("../../examples/extern_code_and_bindings.saturn"),
+        include_str!("../../examples/generators.saturn"),
+        include_str!("../../examples/if_expressions.saturn"),
+        include_str!("../../examples/inheritance.saturn"),
+        include_str!("../../examples/operator_overloading.saturn"),
+        include_str!("../../examples/optional_chaining.saturn"),
+        include_str!("../../examples/try_catch.saturn"),
+    ];
+    for src in examples {
+        assert_eq!(undeclared(ScopeAnalyzer::new(), src), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::{
+    code::{ast_visitor::Visitor, builder::Builder, info::InputFileInfo},
+    lua::{helpers::generate_module_chunk, visitor::LuaEmitter},
+    parser::Script,
+    runtime::{precompile_std_for, StdUsage},
+};
+
+fn get_compiler() -> LuaEmitter {
+    LuaEmitter::new(InputFileInfo {
+        full_path: PathBuf::from("test.saturn"),
+    })
+}
+
+/// Compiles the program with the parts of std it uses, and evaluates it.
+fn eval_shaken(src: &str) -> (String, i64) {
+    let compiler = get_compiler();
+    let main = compiler
+        .visit_script(Builder::new("  "), &Script::parse(src).unwrap())
+        .unwrap()
+        .collect();
+    let (std_src, _) = precompile_std_for(&compiler, &compiler.std_usage.borrow()).unwrap();
+    let code = format!("{}\n{main}", generate_module_chunk(&"std".into(), &std_src));
+    let value = rlua::Lua::new()
+        .context(|ctx| ctx.load(&code).eval())
+        .unwrap();
+    (std_src, value)
+}
+
+#[test]
+fn test_records_std_imports() {
+    let compiler = get_compiler();
+    let script = Script::parse("use { Vector, operators: { `|>`, `..` } } in std;").unwrap();
+    compiler.visit_script(Builder::new("  "), &script).unwrap();
+    let expected = ["Vector", "__saturnus_operator_pipe_greater"]
+        .into_iter()
+        .map(String::from)
+        .chain([crate::parser::helpers::generate_operator_function_name(
+            "..".into(),
+        )])
+        .collect::<HashSet<_>>();
+    assert_eq!(*compiler.std_usage.borrow(), StdUsage::Only(expected));
+    let script = Script::parse("use std;").unwrap();
+    compiler.visit_script(Builder::new("  "), &script).unwrap();
+    assert_eq!(*compiler.std_usage.borrow(), StdUsage::All);
+}
+
+#[test]
+fn test_unused_std_is_left_out() {
+    let (std_src, value) = eval_shaken("return 1;");
+    assert_eq!(value, 1);
+    assert!(!std_src.contains("Vector"));
+    assert!(!std_src.contains("local function"));
+}
+
+#[test]
+fn test_std_keeps_transitive_dependencies() {
+    let (std_src, value) = eval_shaken(
+        "
+        use { Task, operators: { `|>` } } in std;
+        async fn answer() = 42;
+        let task = Task.spawn(answer());
+        Task.run();
+        return task.result |> (x) => x;
+        ",
+    );
+    assert_eq!(value, 42);
+    // The scheduler iterates with Iter, which must come along.
+    assert!(std_src.contains("local Iter"));
+    assert!(!std_src.contains("local Vector"));
+    assert!(!std_src.contains("mixin"));
+    assert!(!std_src.contains("__saturnus_operator_pipe_less"));
+}